@@ -1,25 +1,38 @@
 //! The Cairo backend for the Piet 2D graphics abstraction.
 
 use std::fmt;
+use std::ops::Deref;
 
 use cairo::{
-    BorrowError, Context, Filter, FontFace, FontOptions, FontSlant, FontWeight, Format,
-    ImageSurface, Matrix, Pattern, PatternTrait, ScaledFont, Status, SurfacePattern,
+    prelude::SurfaceExt, BorrowError, Context, Extend, Filter, FontFace, FontOptions, FontSlant,
+    FontWeight, Format, ImageSurface, Matrix, MatrixTrait, Operator, Pattern, PatternTrait,
+    ScaledFont, Status, SurfacePattern,
 };
 
 use kurbo::{Affine, PathEl, QuadBez, Rect, Shape, Vec2};
 
 use piet::{
-    new_error, Error, ErrorKind, FillRule, Font, FontBuilder, Gradient, GradientStop, ImageFormat,
-    InterpolationMode, LineCap, LineJoin, RenderContext, RoundInto, StrokeStyle, Text, TextLayout,
+    affine_inverse, capture_pixel_area, check_image_buf, convert_from_rgba_premul,
+    convert_to_rgba_premul, dither_to_rgba_separate, image_dirty_area, image_src_area,
+    is_drawable_rect, new_error, packed_image_buf, premul, DrawImageOptions, Error, ErrorKind,
+    FillRule, Font, FontBuilder, Gradient, GradientStop, Image, ImageFormat, InterpolationMode,
+    LineCap, LineJoin, Offscreen, RenderContext, RoundInto, StrokeStyle, Text, TextLayout,
     TextLayoutBuilder,
 };
 
 pub struct CairoRenderContext<'a> {
     // Cairo has this as Clone and with &self methods, but we do this to avoid
     // concurrency problems.
-    ctx: &'a mut Context,
+    ctx: ContextRef<'a>,
     text: CairoText,
+    /// The number of saves not yet restored.
+    depth: usize,
+    /// Whether `finish` has been called, after which the context can't be
+    /// used.
+    finished: bool,
+    /// The first error from an operation that can't return one, reported
+    /// by `status` and `finish`.
+    err: Result<(), Error>,
 }
 
 impl<'a> CairoRenderContext<'a> {
@@ -30,10 +43,85 @@ impl<'a> CairoRenderContext<'a> {
     /// need a factory for that as an additional argument.
     pub fn new(ctx: &mut Context) -> CairoRenderContext {
         CairoRenderContext {
-            ctx,
+            ctx: ContextRef::Borrowed(ctx),
             text: CairoText,
+            depth: 0,
+            finished: false,
+            err: Ok(()),
         }
     }
+
+    /// Cairo's own status, which is sticky once an operation fails.
+    fn cairo_status(&self) -> Result<(), Error> {
+        let status = self.ctx.status();
+        if status == Status::Success {
+            Ok(())
+        } else {
+            Err::<(), _>(status).wrap()
+        }
+    }
+
+    /// Record an error from the named operation, unless one is already pending.
+    fn record_err(&mut self, op: &'static str, e: Error) {
+        if self.err.is_ok() {
+            self.err = Err(e.with_op(op));
+        }
+    }
+
+    /// Record an error for the named operation if the context has been
+    /// finished, returning whether it has.
+    fn check_finished(&mut self, op: &'static str) -> bool {
+        if self.finished {
+            self.record_err(op, new_error(ErrorKind::InvalidInput));
+        }
+        self.finished
+    }
+
+    /// Record Cairo's status after the named operation, if it failed.
+    fn check_status(&mut self, op: &'static str) {
+        if let Err(e) = self.cairo_status() {
+            self.record_err(op, e);
+        }
+    }
+}
+
+/// The Cairo context, which is owned by offscreen targets.
+enum ContextRef<'a> {
+    Borrowed(&'a mut Context),
+    Owned(Context),
+}
+
+impl<'a> Deref for ContextRef<'a> {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        match *self {
+            ContextRef::Borrowed(ref ctx) => ctx,
+            ContextRef::Owned(ref ctx) => ctx,
+        }
+    }
+}
+
+/// An image, backed by an image surface.
+///
+/// The surface is `ARgb32`, `Rgb24` or `A8`, so the image also keeps the
+/// format it was made with, which is what `Image::format` reports.
+pub struct CairoImage {
+    surface: ImageSurface,
+    format: ImageFormat,
+}
+
+impl CairoImage {
+    /// The image surface holding the pixels.
+    pub fn surface(&self) -> &ImageSurface {
+        &self.surface
+    }
+}
+
+/// A target for drawing into an image surface.
+pub struct CairoOffscreen {
+    surface: ImageSurface,
+    rc: CairoRenderContext<'static>,
 }
 
 pub enum Brush {
@@ -82,7 +170,7 @@ trait WrapError<T> {
 impl<T> WrapError<T> for Result<T, BorrowError> {
     fn wrap(self) -> Result<T, Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(e);
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
             e.into()
         })
     }
@@ -90,9 +178,13 @@ impl<T> WrapError<T> for Result<T, BorrowError> {
 
 impl<T> WrapError<T> for Result<T, Status> {
     fn wrap(self) -> Result<T, Error> {
-        self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedStatus(e));
-            e.into()
+        self.map_err(|e| match e {
+            Status::NoMemory => new_error(ErrorKind::OutOfMemory),
+            Status::InvalidSize => new_error(ErrorKind::InvalidImageSize),
+            _ => {
+                let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedStatus(e));
+                e.into()
+            }
         })
     }
 }
@@ -104,6 +196,20 @@ fn convert_fill_rule(fill_rule: piet::FillRule) -> cairo::FillRule {
     }
 }
 
+/// The largest width or height of a Cairo image surface.
+const MAX_IMAGE_SIZE: usize = 32767;
+
+/// Check that an image surface of the given size can be created.
+fn check_image_size(width: usize, height: usize) -> Result<(), Error> {
+    if width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+        return Err(new_error(ErrorKind::InvalidImageSize).with_context(format!(
+            "image size {}x{} exceeds Cairo's limit of {}",
+            width, height, MAX_IMAGE_SIZE
+        )));
+    }
+    Ok(())
+}
+
 impl<'a> RenderContext for CairoRenderContext<'a> {
     /// Cairo mostly uses raw f64, so this is as convenient as anything.
     type Point = Vec2;
@@ -113,16 +219,12 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     type Text = CairoText;
     type TextLayout = CairoTextLayout;
 
-    type Image = ImageSurface;
+    type Image = CairoImage;
+    type Offscreen = CairoOffscreen;
 
     fn status(&mut self) -> Result<(), Error> {
-        let status = self.ctx.status();
-        if status == Status::Success {
-            Ok(())
-        } else {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedStatus(status));
-            Err(e.into())
-        }
+        std::mem::replace(&mut self.err, Ok(()))?;
+        self.cairo_status()
     }
 
     fn clear(&mut self, rgb: u32) {
@@ -132,6 +234,7 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
             byte_to_frac(rgb),
         );
         self.ctx.paint();
+        self.check_status("clear");
     }
 
     fn solid_brush(&mut self, rgba: u32) -> Result<Brush, Error> {
@@ -163,12 +266,17 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         self.set_brush(brush);
         self.ctx.set_fill_rule(convert_fill_rule(fill_rule));
         self.ctx.fill();
+        self.check_status("fill");
     }
 
     fn clip(&mut self, shape: impl Shape, fill_rule: FillRule) {
+        if self.check_finished("clip") {
+            return;
+        }
         self.set_path(shape);
         self.ctx.set_fill_rule(convert_fill_rule(fill_rule));
         self.ctx.clip();
+        self.check_status("clip");
     }
 
     fn stroke(
@@ -182,6 +290,7 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         self.set_stroke(width.round_into(), style);
         self.set_brush(brush);
         self.ctx.stroke();
+        self.check_status("stroke");
     }
 
     fn text(&mut self) -> &mut Self::Text {
@@ -199,49 +308,93 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         let pos = pos.round_into();
         self.ctx.move_to(pos.x, pos.y);
         self.ctx.show_text(&layout.text);
+        self.check_status("draw_text");
     }
 
     fn save(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Err(new_error(ErrorKind::InvalidInput));
+        }
         self.ctx.save();
-        self.status()
+        self.depth += 1;
+        self.cairo_status()
     }
 
     fn restore(&mut self) -> Result<(), Error> {
+        if self.depth == 0 {
+            return Err(new_error(ErrorKind::StackUnbalance));
+        }
+        self.depth -= 1;
         self.ctx.restore();
-        self.status()
+        self.cairo_status()
     }
 
     fn finish(&mut self) -> Result<(), Error> {
+        if self.depth != 0 {
+            return Err(new_error(ErrorKind::StackUnbalance));
+        }
+        self.finished = true;
         self.status()
     }
 
     fn transform(&mut self, transform: Affine) {
+        if self.check_finished("transform") {
+            return;
+        }
         self.ctx.transform(affine_to_matrix(transform));
+        self.check_status("transform");
     }
 
-    fn make_image(
+    fn make_image_with_stride(
         &mut self,
         width: usize,
         height: usize,
+        stride: usize,
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
+        if format.bits_per_channel() > 8 {
+            // Images are stored with 8 bits per channel, so dither down.
+            let buf = dither_to_rgba_separate(width, height, stride, buf, format)?;
+            return self.make_image(width, height, &buf, ImageFormat::RgbaSeparate);
+        }
         let cairo_fmt = match format {
-            ImageFormat::Rgb => Format::Rgb24,
-            ImageFormat::RgbaSeparate | ImageFormat::RgbaPremul => Format::ARgb32,
+            ImageFormat::Rgb | ImageFormat::Grayscale => Format::Rgb24,
+            ImageFormat::Alpha8 => Format::A8,
+            ImageFormat::RgbaSeparate
+            | ImageFormat::RgbaPremul
+            | ImageFormat::BgraSeparate
+            | ImageFormat::BgraPremul => Format::ARgb32,
             _ => return Err(new_error(ErrorKind::NotSupported)),
         };
+        check_image_buf(width, height, stride, buf, format)?;
+        check_image_size(width, height)?;
         let mut image = ImageSurface::create(cairo_fmt, width as i32, height as i32).wrap()?;
         // Confident no borrow errors because we just created it.
-        let bytes_per_pixel = format.bytes_per_pixel();
-        let bytes_per_row = width * bytes_per_pixel;
-        let stride = image.get_stride() as usize;
+        let dst_stride = image.get_stride() as usize;
         {
             let mut data = image.get_data().wrap()?;
             for y in 0..height {
-                let src_off = y * bytes_per_row;
-                let dst_off = y * stride;
+                let src_off = y * stride;
+                let dst_off = y * dst_stride;
                 match format {
+                    // These match Cairo's layout, so rows are copied as they are.
+                    ImageFormat::BgraPremul => {
+                        data[dst_off..dst_off + width * 4]
+                            .copy_from_slice(&buf[src_off..src_off + width * 4]);
+                    }
+                    ImageFormat::Alpha8 => {
+                        data[dst_off..dst_off + width]
+                            .copy_from_slice(&buf[src_off..src_off + width]);
+                    }
+                    ImageFormat::Grayscale => {
+                        for x in 0..width {
+                            let g = buf[src_off + x];
+                            data[dst_off + x * 4 + 0] = g;
+                            data[dst_off + x * 4 + 1] = g;
+                            data[dst_off + x * 4 + 2] = g;
+                        }
+                    }
                     ImageFormat::Rgb => {
                         for x in 0..width {
                             data[dst_off + x * 4 + 0] = buf[src_off + x * 3 + 2];
@@ -252,7 +405,6 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
                     ImageFormat::RgbaPremul => {
                         // It's annoying that Cairo exposes only ARGB. Ah well. Let's
                         // hope that LLVM generates pretty good code for this.
-                        // TODO: consider adding BgraPremul format.
                         for x in 0..width {
                             data[dst_off + x * 4 + 0] = buf[src_off + x * 4 + 2];
                             data[dst_off + x * 4 + 1] = buf[src_off + x * 4 + 1];
@@ -261,10 +413,6 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
                         }
                     }
                     ImageFormat::RgbaSeparate => {
-                        fn premul(x: u8, a: u8) -> u8 {
-                            let y = (x as u16) * (a as u16);
-                            ((y + (y >> 8) + 0x80) >> 8) as u8
-                        }
                         for x in 0..width {
                             let a = buf[src_off + x * 4 + 3];
                             data[dst_off + x * 4 + 0] = premul(buf[src_off + x * 4 + 2], a);
@@ -273,11 +421,72 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
                             data[dst_off + x * 4 + 3] = a;
                         }
                     }
+                    ImageFormat::BgraSeparate => {
+                        for x in 0..width {
+                            let a = buf[src_off + x * 4 + 3];
+                            data[dst_off + x * 4 + 0] = premul(buf[src_off + x * 4 + 0], a);
+                            data[dst_off + x * 4 + 1] = premul(buf[src_off + x * 4 + 1], a);
+                            data[dst_off + x * 4 + 2] = premul(buf[src_off + x * 4 + 2], a);
+                            data[dst_off + x * 4 + 3] = a;
+                        }
+                    }
                     _ => return Err(new_error(ErrorKind::NotSupported)),
                 }
             }
         }
-        Ok(image)
+        Ok(CairoImage {
+            surface: image,
+            format,
+        })
+    }
+
+    fn update_image(
+        &mut self,
+        image: &mut Self::Image,
+        dirty_rect: impl Into<Rect>,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let area = image_dirty_area(
+            dirty_rect.into(),
+            image.surface.get_width() as usize,
+            image.surface.get_height() as usize,
+        )?;
+        let (width, height) = (area.width() as usize, area.height() as usize);
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let src_stride = width * format.bytes_per_pixel();
+        if format.bits_per_channel() > 8 {
+            let buf = dither_to_rgba_separate(width, height, src_stride, buf, format)?;
+            return self.update_image(image, area, &buf, ImageFormat::RgbaSeparate);
+        }
+        check_image_buf(width, height, src_stride, buf, format)?;
+        let mut rgba = vec![0; width * height * 4];
+        convert_to_rgba_premul(&buf[..src_stride * height], &mut rgba, format)?;
+        let bytes_per_pixel = match image.surface.get_format() {
+            Format::A8 => 1,
+            Format::ARgb32 | Format::Rgb24 => 4,
+            _ => return Err(new_error(ErrorKind::NotSupported)),
+        };
+        let dst_stride = image.surface.get_stride() as usize;
+        // This fails if the surface is still in use, for example by a pattern.
+        let mut data = image.surface.get_data().wrap()?;
+        for y in 0..height {
+            let dst_off = (area.y0 as usize + y) * dst_stride + area.x0 as usize * bytes_per_pixel;
+            for x in 0..width {
+                let p = &rgba[(y * width + x) * 4..(y * width + x) * 4 + 4];
+                if bytes_per_pixel == 1 {
+                    data[dst_off + x] = p[3];
+                } else {
+                    data[dst_off + x * 4 + 0] = p[2];
+                    data[dst_off + x * 4 + 1] = p[1];
+                    data[dst_off + x * 4 + 2] = p[0];
+                    data[dst_off + x * 4 + 3] = p[3];
+                }
+            }
+        }
+        Ok(())
     }
 
     fn draw_image(
@@ -286,23 +495,218 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
         rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        let _ = self.with_save(|rc| {
-            let surface_pattern = SurfacePattern::create(image);
-            let filter = match interp {
-                InterpolationMode::NearestNeighbor => Filter::Nearest,
-                InterpolationMode::Bilinear => Filter::Bilinear,
-            };
-            surface_pattern.set_filter(filter);
+        self.draw_image_for(
+            "draw_image",
+            image,
+            rect.into(),
+            interp,
+            &DrawImageOptions::new(),
+        );
+    }
+
+    fn draw_image_with_options(
+        &mut self,
+        image: &Self::Image,
+        rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        self.draw_image_for(
+            "draw_image_with_options",
+            image,
+            rect.into(),
+            interp,
+            options,
+        );
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let src_rect = src_rect.into();
+        let dst_rect = dst_rect.into();
+        // A degenerate rectangle would scale by zero or infinity, which
+        // leaves the context in an error state.
+        let area = match image_src_area(
+            src_rect,
+            image.surface.get_width() as usize,
+            image.surface.get_height() as usize,
+        ) {
+            Some(area) if is_drawable_rect(dst_rect) => area,
+            _ => return,
+        };
+        let result = self.with_save(|rc| {
+            // Copy the area, so that padding its edges keeps the rest of the
+            // image out of the filter.
+            let sub_image = ImageSurface::create(
+                image.surface.get_format(),
+                area.width() as i32,
+                area.height() as i32,
+            )
+            .wrap()?;
+            {
+                let cr = Context::new(&sub_image);
+                cr.set_source_surface(&image.surface, -area.x0, -area.y0);
+                cr.set_operator(Operator::Source);
+                cr.paint();
+            }
+            let surface_pattern = SurfacePattern::create(&sub_image);
+            surface_pattern.set_filter(convert_interp(interp));
+            surface_pattern.set_extend(Extend::Pad);
+            // The pattern maps image pixels to the copy.
+            surface_pattern.set_matrix(Matrix::new(1.0, 0.0, 0.0, 1.0, -area.x0, -area.y0));
+            // Work in image pixels from here on.
+            rc.ctx.translate(dst_rect.x0, dst_rect.y0);
+            rc.ctx.scale(
+                dst_rect.width() / src_rect.width(),
+                dst_rect.height() / src_rect.height(),
+            );
+            rc.ctx.translate(-src_rect.x0, -src_rect.y0);
+            rc.ctx.set_source(&Pattern::SurfacePattern(surface_pattern));
+            rc.ctx.rectangle(
+                src_rect.x0,
+                src_rect.y0,
+                src_rect.width(),
+                src_rect.height(),
+            );
+            rc.ctx.fill();
+            rc.cairo_status()
+        });
+        if let Err(e) = result {
+            self.record_err("draw_image_area", e);
+        }
+    }
+
+    fn draw_mask(&mut self, mask: &Self::Image, rect: impl Into<Rect>, brush: &Self::Brush) {
+        let result = self.with_save(|rc| {
+            // The brush is set first, so it isn't affected by the scaling.
+            rc.set_brush(brush);
             let rect = rect.into();
             rc.ctx.translate(rect.x0, rect.y0);
             rc.ctx.scale(
-                rect.width() / (image.get_width() as f64),
-                rect.height() / (image.get_height() as f64),
+                rect.width() / (mask.surface.get_width() as f64),
+                rect.height() / (mask.surface.get_height() as f64),
             );
-            rc.ctx.set_source(&Pattern::SurfacePattern(surface_pattern));
-            rc.ctx.paint();
-            Ok(())
+            let surface_pattern = SurfacePattern::create(&mask.surface);
+            surface_pattern.set_filter(Filter::Bilinear);
+            rc.ctx.mask(&Pattern::SurfacePattern(surface_pattern));
+            rc.cairo_status()
         });
+        if let Err(e) = result {
+            self.record_err("draw_mask", e);
+        }
+    }
+
+    fn create_offscreen(&mut self, width: usize, height: usize) -> Result<Self::Offscreen, Error> {
+        check_image_size(width, height)?;
+        let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32).wrap()?;
+        let rc = CairoRenderContext {
+            ctx: ContextRef::Owned(Context::new(&surface)),
+            text: CairoText,
+            depth: 0,
+            finished: false,
+            err: Ok(()),
+        };
+        Ok(CairoOffscreen { surface, rc })
+    }
+
+    fn capture_image_area(&mut self, rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let area = capture_pixel_area(rect.into())?;
+        check_image_size(area.width() as usize, area.height() as usize)?;
+        let image = ImageSurface::create(Format::ARgb32, area.width() as i32, area.height() as i32)
+            .wrap()?;
+        let target = self.ctx.get_target();
+        target.flush();
+        {
+            let cr = Context::new(&image);
+            cr.set_source_surface(&target, -area.x0, -area.y0);
+            cr.set_operator(Operator::Source);
+            cr.paint();
+        }
+        image.flush();
+        Ok(CairoImage {
+            surface: image,
+            format: ImageFormat::RgbaPremul,
+        })
+    }
+}
+
+impl Image for CairoImage {
+    fn size(&self) -> (usize, usize) {
+        (
+            self.surface.get_width() as usize,
+            self.surface.get_height() as usize,
+        )
+    }
+
+    fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    fn to_raw_pixels(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let (width, height) = self.size();
+        // The surface can't be borrowed mutably here, so copy it; that also
+        // turns every Cairo format into ARGB32.
+        let mut copy = ImageSurface::create(Format::ARgb32, width as i32, height as i32).wrap()?;
+        {
+            let cr = Context::new(&copy);
+            cr.set_source_surface(&self.surface, 0.0, 0.0);
+            cr.set_operator(Operator::Source);
+            cr.paint();
+        }
+        copy.flush();
+        let stride = copy.get_stride() as usize;
+        let data = copy.get_data().wrap()?;
+        // This is how Cairo lays out ARGB32 pixels in memory.
+        let bgra = packed_image_buf(width, height, stride, &data, ImageFormat::BgraPremul);
+        let mut rgba = vec![0; width * height * 4];
+        convert_to_rgba_premul(&bgra, &mut rgba, ImageFormat::BgraPremul)?;
+        if format == ImageFormat::RgbaPremul {
+            return Ok(rgba);
+        }
+        let mut buf = vec![0; width * height * format.bytes_per_pixel()];
+        // As documented on Image, formats without alpha go over black.
+        convert_from_rgba_premul(&rgba, &mut buf, format, 0x00_00_00)?;
+        Ok(buf)
+    }
+}
+
+impl Offscreen for CairoOffscreen {
+    type Image = CairoImage;
+    type Context = CairoRenderContext<'static>;
+
+    fn render_context(&mut self) -> &mut Self::Context {
+        &mut self.rc
+    }
+
+    fn into_image(mut self) -> Result<Self::Image, Error> {
+        self.rc.finish()?;
+        // The surface can only be used as an image once nothing draws on it.
+        let CairoOffscreen { surface, rc } = self;
+        drop(rc);
+        surface.flush();
+        Ok(CairoImage {
+            surface,
+            format: ImageFormat::RgbaPremul,
+        })
+    }
+}
+
+fn convert_interp(interp: InterpolationMode) -> Filter {
+    match interp {
+        InterpolationMode::NearestNeighbor => Filter::Nearest,
+        InterpolationMode::Bilinear => Filter::Bilinear,
+        InterpolationMode::Bicubic => Filter::Best,
+        // Cairo's "good" filter box-filters when scaling down, which is the
+        // part that matters here, and it's faster than "best".
+        InterpolationMode::HighQuality => Filter::Good,
     }
 }
 
@@ -370,6 +774,41 @@ fn convert_line_join(line_join: LineJoin) -> cairo::LineJoin {
 }
 
 impl<'a> CairoRenderContext<'a> {
+    /// Draw an image with options, recording errors for the named operation.
+    fn draw_image_for(
+        &mut self,
+        op: &'static str,
+        image: &CairoImage,
+        rect: Rect,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        // A transform without an inverse, or an empty rectangle, would leave
+        // the context in an error state.
+        if affine_inverse(options.transform()).is_none() || !is_drawable_rect(rect) {
+            return;
+        }
+        let result = self.with_save(|rc| {
+            if let Some(clip) = options.clip() {
+                rc.clip(clip.clone(), FillRule::NonZero);
+            }
+            rc.transform(options.transform());
+            let surface_pattern = SurfacePattern::create(&image.surface);
+            surface_pattern.set_filter(convert_interp(interp));
+            rc.ctx.translate(rect.x0, rect.y0);
+            rc.ctx.scale(
+                rect.width() / (image.surface.get_width() as f64),
+                rect.height() / (image.surface.get_height() as f64),
+            );
+            rc.ctx.set_source(&Pattern::SurfacePattern(surface_pattern));
+            rc.ctx.paint_with_alpha(options.opacity());
+            rc.cairo_status()
+        });
+        if let Err(e) = result {
+            self.record_err(op, e);
+        }
+    }
+
     /// Set the source pattern to the brush.
     ///
     /// Cairo is super stateful, and we're trying to have more retained stuff.
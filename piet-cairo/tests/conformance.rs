@@ -0,0 +1,45 @@
+//! Run the piet conformance probes on Cairo.
+
+use cairo::prelude::SurfaceExt;
+use cairo::{Context, Format, ImageSurface};
+
+use piet::{Error, ImageFormat, RenderContext};
+use piet_cairo::CairoRenderContext;
+use piet_conformance::{assert_conformance, Backend, Pixels, Probe};
+
+fn wrap_err(e: impl std::fmt::Debug) -> Error {
+    let e: Box<dyn std::error::Error + Send + Sync> = format!("Cairo error: {:?}", e).into();
+    e.into()
+}
+
+struct Cairo;
+
+impl Backend for Cairo {
+    fn render(&mut self, width: usize, height: usize, probe: Probe) -> Result<Pixels, Error> {
+        let mut surface =
+            ImageSurface::create(Format::ARgb32, width as i32, height as i32).map_err(wrap_err)?;
+        {
+            let mut cr = Context::new(&surface);
+            let mut rc = CairoRenderContext::new(&mut cr);
+            probe.draw(&mut rc)?;
+            rc.finish()?;
+        }
+        surface.flush();
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().map_err(wrap_err)?;
+        // Cairo stores premultiplied BGRA on little-endian machines.
+        let bgra = piet::packed_image_buf(width, height, stride, &data, ImageFormat::BgraPremul);
+        let mut rgba = vec![0; width * height * 4];
+        piet::convert_to_rgba_premul(&bgra, &mut rgba, ImageFormat::BgraPremul)?;
+        Ok(Pixels {
+            width,
+            height,
+            data: rgba,
+        })
+    }
+}
+
+#[test]
+fn conformance() {
+    assert_conformance(&mut Cairo, &[]);
+}
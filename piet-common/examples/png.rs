@@ -1,6 +1,6 @@
 use kurbo::Line;
 
-use piet::{ImageFormat, RenderContext};
+use piet::RenderContext;
 use piet_common::Device;
 
 fn main() {
@@ -13,13 +13,5 @@ fn main() {
     let brush = rc.solid_brush(0x00_00_80_FF).unwrap();
     rc.stroke(Line::new((10.0, 10.0), (100.0, 50.0)), &brush, 1.0, None);
     rc.finish().unwrap();
-    let raw_pixels = bitmap.into_raw_pixels(ImageFormat::RgbaPremul).unwrap();
-    image::save_buffer(
-        "temp-image.png",
-        &raw_pixels,
-        width as u32,
-        height as u32,
-        image::ColorType::RGBA(8),
-    )
-    .unwrap();
+    bitmap.save_to_file("temp-image.png").unwrap();
 }
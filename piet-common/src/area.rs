@@ -0,0 +1,58 @@
+//! Areas of bitmap targets for pixel readback.
+
+use kurbo::Rect;
+
+use piet::{new_error, Error, ErrorKind, ImageFormat};
+
+/// A rectangle of whole pixels within a bitmap.
+#[derive(Clone, Copy)]
+pub(crate) struct PixelArea {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl PixelArea {
+    /// The area covering a whole bitmap.
+    pub fn full(width: usize, height: usize) -> PixelArea {
+        PixelArea {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Round a rectangle out to whole pixels.
+    ///
+    /// It is an error for the rectangle to extend past the bitmap.
+    pub fn from_rect(rect: Rect, width: usize, height: usize) -> Result<PixelArea, Error> {
+        let x0 = rect.x0.min(rect.x1).floor();
+        let y0 = rect.y0.min(rect.y1).floor();
+        let x1 = rect.x0.max(rect.x1).ceil();
+        let y1 = rect.y0.max(rect.y1).ceil();
+        if !(x0 >= 0.0 && y0 >= 0.0 && x1 <= width as f64 && y1 <= height as f64) {
+            return Err(new_error(ErrorKind::InvalidInput));
+        }
+        Ok(PixelArea {
+            x: x0 as usize,
+            y: y0 as usize,
+            width: (x1 - x0) as usize,
+            height: (y1 - y0) as usize,
+        })
+    }
+
+    /// The number of bytes in one row of the area in the given format.
+    pub fn bytes_per_row(&self, fmt: ImageFormat) -> usize {
+        self.width * fmt.bytes_per_pixel()
+    }
+
+    /// Check that a buffer has exactly the size of the area in the given format.
+    pub fn check_buf(&self, fmt: ImageFormat, buf: &[u8]) -> Result<(), Error> {
+        if buf.len() != self.height * self.bytes_per_row(fmt) {
+            return Err(new_error(ErrorKind::InvalidInput));
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,19 @@
 //! Support for piet Cairo back-end.
 
+#[cfg(feature = "png")]
+use std::io::Write;
 use std::marker::PhantomData;
+#[cfg(feature = "png")]
+use std::path::Path;
 
 use cairo::prelude::SurfaceExt;
-use cairo::{Context, Format, ImageSurface};
+use cairo::{Context, Format, ImageSurface, Operator};
 
-use piet::{ErrorKind, ImageFormat};
+use kurbo::Rect;
+
+use piet::ImageFormat;
+
+use crate::area::PixelArea;
 
 pub use piet_cairo::*;
 
@@ -21,9 +29,15 @@ pub struct Device;
 pub struct BitmapTarget<'a> {
     surface: ImageSurface,
     cr: Context,
+    pix_scale: f64,
     phantom: PhantomData<&'a ()>,
 }
 
+fn wrap_status(status: cairo::Status) -> piet::Error {
+    let e: Box<dyn std::error::Error + Send + Sync> = format!("Cairo error: {:?}", status).into();
+    e.into()
+}
+
 impl Device {
     /// Create a new device.
     pub fn new() -> Result<Device, piet::Error> {
@@ -37,13 +51,15 @@ impl Device {
         height: usize,
         pix_scale: f64,
     ) -> Result<BitmapTarget, piet::Error> {
-        let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32).unwrap();
+        let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32)
+            .map_err(wrap_status)?;
         let cr = Context::new(&surface);
         cr.scale(pix_scale, pix_scale);
         let phantom = Default::default();
         Ok(BitmapTarget {
             surface,
             cr,
+            pix_scale,
             phantom,
         })
     }
@@ -58,32 +74,184 @@ impl<'a> BitmapTarget<'a> {
         CairoRenderContext::new(&mut self.cr)
     }
 
-    /// Get raw RGBA pixels from the bitmap.
+    /// Get raw pixels from the bitmap.
+    ///
+    /// Formats without alpha are composited over black; see
+    /// [`into_raw_pixels_with_background`](#method.into_raw_pixels_with_background).
     pub fn into_raw_pixels(mut self, fmt: ImageFormat) -> Result<Vec<u8>, piet::Error> {
-        // TODO: convert other formats.
-        if fmt != ImageFormat::RgbaPremul {
-            return Err(piet::new_error(ErrorKind::NotSupported));
-        }
-        std::mem::drop(self.cr);
+        self.read_pixels(
+            PixelArea::full(
+                self.surface.get_width() as usize,
+                self.surface.get_height() as usize,
+            ),
+            fmt,
+            0x00_00_00,
+        )
+    }
+
+    /// Get raw pixels from the bitmap, compositing over a background color.
+    ///
+    /// The `background` is an RGB color, and is only used by formats without
+    /// an alpha channel.
+    pub fn into_raw_pixels_with_background(
+        mut self,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        self.read_pixels(
+            PixelArea::full(
+                self.surface.get_width() as usize,
+                self.surface.get_height() as usize,
+            ),
+            fmt,
+            background,
+        )
+    }
+
+    /// Copy raw pixels from the bitmap into a buffer.
+    ///
+    /// Unlike [`into_raw_pixels`](#method.into_raw_pixels), this leaves the
+    /// target intact, so rendering can continue afterwards. The buffer must
+    /// hold exactly the bitmap's pixels in the requested format.
+    pub fn copy_raw_pixels(&mut self, fmt: ImageFormat, buf: &mut [u8]) -> Result<(), piet::Error> {
+        let area = PixelArea::full(
+            self.surface.get_width() as usize,
+            self.surface.get_height() as usize,
+        );
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    /// Copy raw pixels from an area of the bitmap into a buffer.
+    ///
+    /// The area is in pixels, and is rounded out to whole pixels. It must lie
+    /// within the bitmap, and the buffer must hold exactly its pixels.
+    pub fn copy_raw_pixels_area(
+        &mut self,
+        rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        let area = PixelArea::from_rect(
+            rect.into(),
+            self.surface.get_width() as usize,
+            self.surface.get_height() as usize,
+        )?;
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    fn read_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = vec![0; area.height * area.bytes_per_row(fmt)];
+        self.copy_pixels(area, fmt, background, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn copy_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        // The surface can't be borrowed while the context refers to it, so
+        // copy the area into a scratch surface first.
         self.surface.flush();
-        let stride = self.surface.get_stride() as usize;
-        let width = self.surface.get_width() as usize;
-        let height = self.surface.get_height() as usize;
-        let mut raw_data = vec![0; width * height * 4];
-        let buf = self
-            .surface
+        let mut scratch =
+            ImageSurface::create(Format::ARgb32, area.width as i32, area.height as i32)
+                .map_err(wrap_status)?;
+        {
+            let cr = Context::new(&scratch);
+            cr.set_source_surface(&self.surface, -(area.x as f64), -(area.y as f64));
+            cr.set_operator(Operator::Source);
+            cr.paint();
+        }
+        scratch.flush();
+        let stride = scratch.get_stride() as usize;
+        let bytes_per_row = area.bytes_per_row(fmt);
+        let mut row = vec![0; area.width * 4];
+        let data = scratch
             .get_data()
-            .map_err(|e| Into::<Box<dyn std::error::Error>>::into(e))?;
-        for y in 0..height {
+            .map_err(|e| Into::<Box<dyn std::error::Error + Send + Sync>>::into(e))?;
+        for y in 0..area.height {
             let src_off = y * stride;
-            let dst_off = y * width * 4;
-            for x in 0..width {
-                raw_data[dst_off + x * 4 + 0] = buf[src_off + x * 4 + 2];
-                raw_data[dst_off + x * 4 + 1] = buf[src_off + x * 4 + 1];
-                raw_data[dst_off + x * 4 + 2] = buf[src_off + x * 4 + 0];
-                raw_data[dst_off + x * 4 + 3] = buf[src_off + x * 4 + 3];
+            let dst_off = y * bytes_per_row;
+            if fmt == ImageFormat::BgraPremul {
+                // This is how Cairo lays out ARGB32 pixels in memory.
+                buf[dst_off..dst_off + bytes_per_row]
+                    .copy_from_slice(&data[src_off..src_off + bytes_per_row]);
+                continue;
             }
+            piet::convert_to_rgba_premul(
+                &data[src_off..src_off + area.width * 4],
+                &mut row,
+                ImageFormat::BgraPremul,
+            )?;
+            piet::convert_from_rgba_premul(
+                &row,
+                &mut buf[dst_off..dst_off + bytes_per_row],
+                fmt,
+                background,
+            )?;
         }
-        Ok(raw_data)
+        Ok(())
+    }
+
+    /// Save the bitmap as a PNG file.
+    ///
+    /// The pixels are written with separate alpha, without a physical
+    /// resolution; see [`save_to_file_with_dpi`](#method.save_to_file_with_dpi).
+    /// Only PNG is supported, so a path with another extension is reported
+    /// as `ErrorKind::NotSupported`.
+    #[cfg(feature = "png")]
+    pub fn save_to_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, false)
+    }
+
+    /// Save the bitmap as a PNG file, recording its resolution.
+    ///
+    /// The resolution is 96 dpi times the `pix_scale` the target was
+    /// created with, so that image viewers can show the bitmap at its
+    /// intended size. As with [`save_to_file`](#method.save_to_file), the
+    /// path must name a PNG file.
+    #[cfg(feature = "png")]
+    pub fn save_to_file_with_dpi<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, true)
+    }
+
+    /// Write the bitmap in PNG format.
+    ///
+    /// See [`save_to_file`](#method.save_to_file) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, false)
+    }
+
+    /// Write the bitmap in PNG format, recording its resolution.
+    ///
+    /// See [`save_to_file_with_dpi`](#method.save_to_file_with_dpi) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png_with_dpi<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, true)
+    }
+
+    #[cfg(feature = "png")]
+    fn write_png<W: Write>(&mut self, writer: W, with_dpi: bool) -> Result<(), piet::Error> {
+        let (width, height, pix_scale) = (
+            self.surface.get_width() as usize,
+            self.surface.get_height() as usize,
+            self.pix_scale,
+        );
+        let area = PixelArea::full(width, height);
+        let pixels = self.read_pixels(area, ImageFormat::RgbaSeparate, 0x00_00_00)?;
+        let pix_scale = if with_dpi { Some(pix_scale) } else { None };
+        crate::save::write_png(writer, width, height, pix_scale, &pixels)
     }
 }
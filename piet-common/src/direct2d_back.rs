@@ -1,6 +1,10 @@
 //! Support for piet Direct2D back-end.
 
 use std::fmt;
+#[cfg(feature = "png")]
+use std::io::Write;
+#[cfg(feature = "png")]
+use std::path::Path;
 
 use direct2d::enums::BitmapOptions;
 use direct2d::image::Bitmap;
@@ -10,10 +14,14 @@ use direct3d11::flags::{BindFlags, CreateDeviceFlags};
 use direct3d11::helpers::ComWrapper;
 use dxgi::flags::Format;
 
-use piet::{ErrorKind, ImageFormat};
+use kurbo::Rect;
+
+use piet::ImageFormat;
 
 pub use piet_direct2d::*;
 
+use crate::area::PixelArea;
+
 /// The `RenderContext` for the Direct2D backend, which is selected.
 pub type Piet<'a> = D2DRenderContext<'a>;
 
@@ -30,6 +38,7 @@ pub struct Device {
 pub struct BitmapTarget<'a> {
     width: usize,
     height: usize,
+    pix_scale: f64,
     d2d: &'a direct2d::Factory,
     dwrite: &'a directwrite::Factory,
     d3d: &'a direct3d11::Device,
@@ -76,7 +85,7 @@ impl fmt::Display for WrappedDxgiError {
 impl<T> WrapError<T> for Result<T, (direct2d::Error, Option<RenderTag>)> {
     fn wrap(self) -> Result<T, piet::Error> {
         self.map_err(|(e, t)| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedD2DTag(e, t));
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedD2DTag(e, t));
             e.into()
         })
     }
@@ -85,7 +94,7 @@ impl<T> WrapError<T> for Result<T, (direct2d::Error, Option<RenderTag>)> {
 impl<T> WrapError<T> for Result<T, direct3d11::Error> {
     fn wrap(self) -> Result<T, piet::Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedD3D11Error(e));
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedD3D11Error(e));
             e.into()
         })
     }
@@ -94,7 +103,7 @@ impl<T> WrapError<T> for Result<T, direct3d11::Error> {
 impl<T> WrapError<T> for Result<T, dxgi::Error> {
     fn wrap(self) -> Result<T, piet::Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedDxgiError(e));
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedDxgiError(e));
             e.into()
         })
     }
@@ -158,6 +167,7 @@ impl Device {
         Ok(BitmapTarget {
             width,
             height,
+            pix_scale,
             d2d: &self.d2d,
             dwrite: &self.dwrite,
             d3d: &self.d3d,
@@ -177,13 +187,89 @@ impl<'a> BitmapTarget<'a> {
         D2DRenderContext::new(self.d2d, self.dwrite, &mut self.context)
     }
 
-    /// Get raw RGBA pixels from the bitmap.
+    /// Get raw pixels from the bitmap.
+    ///
+    /// Formats without alpha are composited over black; see
+    /// [`into_raw_pixels_with_background`](#method.into_raw_pixels_with_background).
     pub fn into_raw_pixels(mut self, fmt: ImageFormat) -> Result<Vec<u8>, piet::Error> {
-        // TODO: convert other formats.
-        if fmt != ImageFormat::RgbaPremul {
-            return Err(piet::new_error(ErrorKind::NotSupported));
-        }
-        self.context.end_draw().wrap()?;
+        self.read_pixels(PixelArea::full(self.width, self.height), fmt, 0x00_00_00)
+    }
+
+    /// Get raw pixels from the bitmap, compositing over a background color.
+    ///
+    /// The `background` is an RGB color, and is only used by formats without
+    /// an alpha channel.
+    pub fn into_raw_pixels_with_background(
+        mut self,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        self.read_pixels(PixelArea::full(self.width, self.height), fmt, background)
+    }
+
+    /// Copy raw pixels from the bitmap into a buffer.
+    ///
+    /// Unlike [`into_raw_pixels`](#method.into_raw_pixels), this leaves the
+    /// target intact, so rendering can continue afterwards. The buffer must
+    /// hold exactly the bitmap's pixels in the requested format.
+    pub fn copy_raw_pixels(&mut self, fmt: ImageFormat, buf: &mut [u8]) -> Result<(), piet::Error> {
+        let area = PixelArea::full(self.width, self.height);
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    /// Copy raw pixels from an area of the bitmap into a buffer.
+    ///
+    /// The area is in pixels, and is rounded out to whole pixels. It must lie
+    /// within the bitmap, and the buffer must hold exactly its pixels.
+    pub fn copy_raw_pixels_area(
+        &mut self,
+        rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        let area = PixelArea::from_rect(rect.into(), self.width, self.height)?;
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    fn read_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = vec![0; area.height * area.bytes_per_row(fmt)];
+        self.copy_pixels(area, fmt, background, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn copy_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        let result = self
+            .context
+            .end_draw()
+            .wrap()
+            .and_then(|()| self.copy_texture_pixels(area, fmt, background, buf));
+        // Resume drawing whether or not the copy worked, so that the target
+        // can keep being rendered to.
+        self.context.begin_draw();
+        result
+    }
+
+    /// Copy pixels from the texture, which must not be drawing.
+    fn copy_texture_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
         let temp_texture = direct3d11::texture2d::Texture2D::create(self.d3d)
             .with_size(self.width as u32, self.height as u32)
             .with_format(direct3d11::flags::Format::R8G8B8A8Unorm)
@@ -193,8 +279,8 @@ impl<'a> BitmapTarget<'a> {
             .build()
             .wrap()?;
 
+        let bytes_per_row = area.bytes_per_row(fmt);
         // TODO: Have a safe way to accomplish this :D
-        let mut raw_pixels: Vec<u8> = Vec::with_capacity(self.width * self.height * 4);
         unsafe {
             let ctx = &*self.d3d_ctx.get_raw();
             ctx.CopyResource(
@@ -205,10 +291,66 @@ impl<'a> BitmapTarget<'a> {
 
             let surface = temp_texture.as_dxgi();
             let map = surface.map(true, false, false).wrap()?;
-            for y in 0..(self.height as u32) {
-                raw_pixels.extend_from_slice(&map.row(y)[..self.width * 4]);
+            for y in 0..area.height {
+                let row = map.row((area.y + y) as u32);
+                let dst_off = y * bytes_per_row;
+                piet::convert_from_rgba_premul(
+                    &row[area.x * 4..(area.x + area.width) * 4],
+                    &mut buf[dst_off..dst_off + bytes_per_row],
+                    fmt,
+                    background,
+                )?;
             }
         }
-        Ok(raw_pixels)
+        Ok(())
+    }
+
+    /// Save the bitmap as a PNG file.
+    ///
+    /// The pixels are written with separate alpha, without a physical
+    /// resolution; see [`save_to_file_with_dpi`](#method.save_to_file_with_dpi).
+    /// Only PNG is supported, so a path with another extension is reported
+    /// as `ErrorKind::NotSupported`.
+    #[cfg(feature = "png")]
+    pub fn save_to_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, false)
+    }
+
+    /// Save the bitmap as a PNG file, recording its resolution.
+    ///
+    /// The resolution is 96 dpi times the `pix_scale` the target was
+    /// created with, so that image viewers can show the bitmap at its
+    /// intended size. As with [`save_to_file`](#method.save_to_file), the
+    /// path must name a PNG file.
+    #[cfg(feature = "png")]
+    pub fn save_to_file_with_dpi<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, true)
+    }
+
+    /// Write the bitmap in PNG format.
+    ///
+    /// See [`save_to_file`](#method.save_to_file) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, false)
+    }
+
+    /// Write the bitmap in PNG format, recording its resolution.
+    ///
+    /// See [`save_to_file_with_dpi`](#method.save_to_file_with_dpi) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png_with_dpi<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, true)
+    }
+
+    #[cfg(feature = "png")]
+    fn write_png<W: Write>(&mut self, writer: W, with_dpi: bool) -> Result<(), piet::Error> {
+        let (width, height, pix_scale) = (self.width, self.height, self.pix_scale);
+        let area = PixelArea::full(width, height);
+        let pixels = self.read_pixels(area, ImageFormat::RgbaSeparate, 0x00_00_00)?;
+        let pix_scale = if with_dpi { Some(pix_scale) } else { None };
+        crate::save::write_png(writer, width, height, pix_scale, &pixels)
     }
 }
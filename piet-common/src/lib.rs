@@ -1,23 +1,62 @@
 //! Selection of a common back-end for piet.
+//!
+//! The back-end is chosen based on the target platform: Direct2D on Windows,
+//! the web canvas on wasm, and Cairo elsewhere. Enabling the `raqote` feature
+//! selects the pure Rust Raqote back-end instead.
 
+// Used by every back-end with a bitmap target, so not the web.
 #[cfg(any(
+    all(
+        feature = "cairo",
+        not(any(
+            target_arch = "wasm32",
+            target_os = "windows",
+            feature = "d2d",
+            feature = "raqote"
+        ))
+    ),
+    feature = "d2d",
+    all(target_os = "windows", not(feature = "raqote")),
+    feature = "raqote"
+))]
+mod area;
+#[cfg(feature = "png")]
+mod save;
+
+#[cfg(all(
     feature = "cairo",
-    not(any(target_arch = "wasm32", target_os = "windows", feature = "direct2d"))
+    not(any(
+        target_arch = "wasm32",
+        target_os = "windows",
+        feature = "d2d",
+        feature = "raqote"
+    ))
 ))]
 mod cairo_back;
 
-#[cfg(any(
+#[cfg(all(
     feature = "cairo",
-    not(any(target_arch = "wasm32", target_os = "windows", feature = "direct2d"))
+    not(any(
+        target_arch = "wasm32",
+        target_os = "windows",
+        feature = "d2d",
+        feature = "raqote"
+    ))
 ))]
 pub use crate::cairo_back::*;
 
-#[cfg(any(feature = "d2d", all(target_os = "windows", not(feature = "cairo"))))]
+#[cfg(any(feature = "d2d", all(target_os = "windows", not(feature = "raqote"))))]
 mod direct2d_back;
 
-#[cfg(any(feature = "d2d", all(target_os = "windows", not(feature = "cairo"))))]
+#[cfg(any(feature = "d2d", all(target_os = "windows", not(feature = "raqote"))))]
 pub use crate::direct2d_back::*;
 
+#[cfg(all(feature = "raqote", not(feature = "d2d")))]
+mod raqote_back;
+
+#[cfg(all(feature = "raqote", not(feature = "d2d")))]
+pub use crate::raqote_back::*;
+
 #[cfg(any(feature = "web", target_arch = "wasm32"))]
 mod back {
     pub use piet_web::*;
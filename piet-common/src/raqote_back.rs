@@ -0,0 +1,202 @@
+//! Support for piet Raqote back-end.
+
+#[cfg(feature = "png")]
+use std::io::Write;
+use std::marker::PhantomData;
+#[cfg(feature = "png")]
+use std::path::Path;
+
+use kurbo::{Affine, Rect};
+
+use raqote_rs::DrawTarget;
+
+use piet::{ImageFormat, RenderContext};
+
+pub use piet_raqote::*;
+
+use crate::area::PixelArea;
+
+/// The `RenderContext` for the Raqote backend, which is selected.
+pub type Piet<'a> = RaqoteRenderContext<'a>;
+
+/// A struct that can be used to create bitmap render contexts.
+///
+/// In the case of Raqote, being a software renderer, no state is needed.
+pub struct Device;
+
+/// A struct provides a `RenderContext` and then can have its bitmap extracted.
+pub struct BitmapTarget<'a> {
+    draw_target: DrawTarget,
+    width: usize,
+    height: usize,
+    pix_scale: f64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl Device {
+    /// Create a new device.
+    pub fn new() -> Result<Device, piet::Error> {
+        Ok(Device)
+    }
+
+    /// Create a new bitmap target.
+    pub fn bitmap_target(
+        &self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+    ) -> Result<BitmapTarget, piet::Error> {
+        let draw_target = DrawTarget::new(width as i32, height as i32);
+        let phantom = Default::default();
+        Ok(BitmapTarget {
+            draw_target,
+            width,
+            height,
+            pix_scale,
+            phantom,
+        })
+    }
+}
+
+impl<'a> BitmapTarget<'a> {
+    /// Get a piet `RenderContext` for the bitmap.
+    ///
+    /// Note: caller is responsible for calling `finish` on the render
+    /// context at the end of rendering.
+    pub fn render_context<'b>(&'b mut self) -> RaqoteRenderContext<'b> {
+        let mut rc = RaqoteRenderContext::new(&mut self.draw_target);
+        rc.transform(Affine::scale(self.pix_scale));
+        rc
+    }
+
+    /// Get raw pixels from the bitmap.
+    ///
+    /// Formats without alpha are composited over black; see
+    /// [`into_raw_pixels_with_background`](#method.into_raw_pixels_with_background).
+    pub fn into_raw_pixels(mut self, fmt: ImageFormat) -> Result<Vec<u8>, piet::Error> {
+        self.read_pixels(PixelArea::full(self.width, self.height), fmt, 0x00_00_00)
+    }
+
+    /// Get raw pixels from the bitmap, compositing over a background color.
+    ///
+    /// The `background` is an RGB color, and is only used by formats without
+    /// an alpha channel.
+    pub fn into_raw_pixels_with_background(
+        mut self,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        self.read_pixels(PixelArea::full(self.width, self.height), fmt, background)
+    }
+
+    /// Copy raw pixels from the bitmap into a buffer.
+    ///
+    /// Unlike [`into_raw_pixels`](#method.into_raw_pixels), this leaves the
+    /// target intact, so rendering can continue afterwards. The buffer must
+    /// hold exactly the bitmap's pixels in the requested format.
+    pub fn copy_raw_pixels(&mut self, fmt: ImageFormat, buf: &mut [u8]) -> Result<(), piet::Error> {
+        let area = PixelArea::full(self.width, self.height);
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    /// Copy raw pixels from an area of the bitmap into a buffer.
+    ///
+    /// The area is in pixels, and is rounded out to whole pixels. It must lie
+    /// within the bitmap, and the buffer must hold exactly its pixels.
+    pub fn copy_raw_pixels_area(
+        &mut self,
+        rect: impl Into<Rect>,
+        fmt: ImageFormat,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        let area = PixelArea::from_rect(rect.into(), self.width, self.height)?;
+        area.check_buf(fmt, buf)?;
+        self.copy_pixels(area, fmt, 0x00_00_00, buf)
+    }
+
+    fn read_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+    ) -> Result<Vec<u8>, piet::Error> {
+        let mut buf = vec![0; area.height * area.bytes_per_row(fmt)];
+        self.copy_pixels(area, fmt, background, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn copy_pixels(
+        &mut self,
+        area: PixelArea,
+        fmt: ImageFormat,
+        background: u32,
+        buf: &mut [u8],
+    ) -> Result<(), piet::Error> {
+        // Raqote stores premultiplied ARGB as native u32 values.
+        let data = self.draw_target.get_data();
+        let bytes_per_row = area.bytes_per_row(fmt);
+        let mut row = vec![0; area.width * 4];
+        for y in 0..area.height {
+            let src_off = (area.y + y) * self.width + area.x;
+            let dst_off = y * bytes_per_row;
+            piet::argb32_to_rgba_premul(&data[src_off..src_off + area.width], &mut row)?;
+            piet::convert_from_rgba_premul(
+                &row,
+                &mut buf[dst_off..dst_off + bytes_per_row],
+                fmt,
+                background,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Save the bitmap as a PNG file.
+    ///
+    /// The pixels are written with separate alpha, without a physical
+    /// resolution; see [`save_to_file_with_dpi`](#method.save_to_file_with_dpi).
+    /// Only PNG is supported, so a path with another extension is reported
+    /// as `ErrorKind::NotSupported`.
+    #[cfg(feature = "png")]
+    pub fn save_to_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, false)
+    }
+
+    /// Save the bitmap as a PNG file, recording its resolution.
+    ///
+    /// The resolution is 96 dpi times the `pix_scale` the target was
+    /// created with, so that image viewers can show the bitmap at its
+    /// intended size. As with [`save_to_file`](#method.save_to_file), the
+    /// path must name a PNG file.
+    #[cfg(feature = "png")]
+    pub fn save_to_file_with_dpi<P: AsRef<Path>>(&mut self, path: P) -> Result<(), piet::Error> {
+        let file = crate::save::create_file(path.as_ref())?;
+        self.write_png(file, true)
+    }
+
+    /// Write the bitmap in PNG format.
+    ///
+    /// See [`save_to_file`](#method.save_to_file) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, false)
+    }
+
+    /// Write the bitmap in PNG format, recording its resolution.
+    ///
+    /// See [`save_to_file_with_dpi`](#method.save_to_file_with_dpi) for details.
+    #[cfg(feature = "png")]
+    pub fn save_png_with_dpi<W: Write>(&mut self, writer: W) -> Result<(), piet::Error> {
+        self.write_png(writer, true)
+    }
+
+    #[cfg(feature = "png")]
+    fn write_png<W: Write>(&mut self, writer: W, with_dpi: bool) -> Result<(), piet::Error> {
+        let (width, height, pix_scale) = (self.width, self.height, self.pix_scale);
+        let area = PixelArea::full(width, height);
+        let pixels = self.read_pixels(area, ImageFormat::RgbaSeparate, 0x00_00_00)?;
+        let pix_scale = if with_dpi { Some(pix_scale) } else { None };
+        crate::save::write_png(writer, width, height, pix_scale, &pixels)
+    }
+}
@@ -0,0 +1,68 @@
+//! Saving bitmap targets as PNG images.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use png::HasParameters;
+
+use piet::{new_error, Error, ErrorKind};
+
+/// The resolution of a bitmap with a `pix_scale` of 1.0.
+const BASE_DPI: f64 = 96.0;
+
+const METERS_PER_INCH: f64 = 0.0254;
+
+fn wrap_err(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    let e: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
+    e.into()
+}
+
+/// Create a file for writing a PNG.
+///
+/// Paths with an extension other than `png` are rejected before anything is
+/// created, since the file would hold PNG data anyway.
+pub(crate) fn create_file(path: &Path) -> Result<BufWriter<File>, Error> {
+    if let Some(ext) = path.extension() {
+        if !ext.to_string_lossy().eq_ignore_ascii_case("png") {
+            return Err(new_error(ErrorKind::NotSupported)
+                .with_context(format!("can only save PNG files, not {}", path.display())));
+        }
+    }
+    File::create(path).map(BufWriter::new).map_err(wrap_err)
+}
+
+/// Encode RGBA pixels with separate alpha as an 8-bit sRGB PNG.
+///
+/// With a `pix_scale`, the physical resolution is recorded so that image
+/// viewers can show the bitmap at its intended size.
+pub(crate) fn write_png<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    pix_scale: Option<f64>,
+    rgba: &[u8],
+) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(wrap_err)?;
+
+    // sRGB with the perceptual rendering intent.
+    writer.write_chunk(*b"sRGB", &[0]).map_err(wrap_err)?;
+
+    if let Some(pix_scale) = pix_scale {
+        let pixels_per_meter = (BASE_DPI * pix_scale / METERS_PER_INCH).round() as u32;
+        let mut phys = [0; 9];
+        for i in 0..4 {
+            let byte = (pixels_per_meter >> (24 - 8 * i)) as u8;
+            // Same resolution horizontally and vertically.
+            phys[i] = byte;
+            phys[i + 4] = byte;
+        }
+        // The unit is the meter.
+        phys[8] = 1;
+        writer.write_chunk(*b"pHYs", &phys).map_err(wrap_err)?;
+    }
+
+    writer.write_image_data(rgba).map_err(wrap_err)
+}
@@ -0,0 +1,94 @@
+//! Reading pixels back from bitmap targets without consuming them.
+
+use kurbo::Rect;
+
+use piet::{ErrorKind, FillRule, ImageFormat, RenderContext};
+use piet_common::{BitmapTarget, Device};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+/// Fill a rectangle of the target with an opaque color.
+fn fill(bitmap: &mut BitmapTarget, rect: Rect, rgba: u32) {
+    let mut rc = bitmap.render_context();
+    let brush = rc.solid_brush(rgba).unwrap();
+    rc.fill(rect, &brush, FillRule::NonZero);
+    rc.finish().unwrap();
+}
+
+fn pixel(buf: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+    let ix = (y * width + x) * 4;
+    &buf[ix..ix + 4]
+}
+
+#[test]
+fn copy_raw_pixels_allows_more_drawing() {
+    let device = Device::new().unwrap();
+    let mut bitmap = device.bitmap_target(4, 2, 1.0).unwrap();
+    fill(&mut bitmap, Rect::new(0.0, 0.0, 4.0, 2.0), 0xff_ff_ff_ff);
+    fill(&mut bitmap, Rect::new(0.0, 0.0, 2.0, 2.0), 0xff_00_00_ff);
+    let mut buf = vec![0; 4 * 2 * 4];
+    bitmap
+        .copy_raw_pixels(ImageFormat::RgbaPremul, &mut buf)
+        .unwrap();
+    assert_eq!(pixel(&buf, 4, 0, 0), RED);
+    assert_eq!(pixel(&buf, 4, 3, 1), WHITE);
+
+    fill(&mut bitmap, Rect::new(2.0, 0.0, 4.0, 2.0), 0x00_00_ff_ff);
+    bitmap
+        .copy_raw_pixels(ImageFormat::RgbaPremul, &mut buf)
+        .unwrap();
+    assert_eq!(pixel(&buf, 4, 0, 0), RED);
+    assert_eq!(pixel(&buf, 4, 3, 1), BLUE);
+}
+
+#[test]
+fn copy_raw_pixels_area_converts() {
+    let device = Device::new().unwrap();
+    let mut bitmap = device.bitmap_target(4, 2, 1.0).unwrap();
+    fill(&mut bitmap, Rect::new(0.0, 0.0, 4.0, 2.0), 0xff_ff_ff_ff);
+    fill(&mut bitmap, Rect::new(0.0, 0.0, 2.0, 2.0), 0xff_00_00_ff);
+    let mut buf = vec![0; 2 * 3];
+    bitmap
+        .copy_raw_pixels_area(Rect::new(1.0, 1.0, 3.0, 2.0), ImageFormat::Rgb, &mut buf)
+        .unwrap();
+    assert_eq!(buf, [255, 0, 0, 255, 255, 255]);
+    // Fractional areas are rounded out to whole pixels.
+    let mut buf = vec![0; 2 * 2 * 4];
+    bitmap
+        .copy_raw_pixels_area(
+            Rect::new(1.5, 0.5, 2.5, 1.5),
+            ImageFormat::RgbaSeparate,
+            &mut buf,
+        )
+        .unwrap();
+    assert_eq!(pixel(&buf, 2, 0, 0), RED);
+    assert_eq!(pixel(&buf, 2, 1, 1), WHITE);
+}
+
+#[test]
+fn copy_raw_pixels_rejects_bad_input() {
+    let device = Device::new().unwrap();
+    let mut bitmap = device.bitmap_target(4, 2, 1.0).unwrap();
+    let mut short = vec![0; 4 * 2 * 4 - 1];
+    let e = bitmap
+        .copy_raw_pixels(ImageFormat::RgbaPremul, &mut short)
+        .unwrap_err();
+    assert!(match e.kind() {
+        ErrorKind::InvalidInput => true,
+        _ => false,
+    });
+    let mut buf = vec![0; 2 * 4];
+    let e = bitmap
+        .copy_raw_pixels_area(
+            Rect::new(3.0, 0.0, 5.0, 1.0),
+            ImageFormat::RgbaPremul,
+            &mut buf,
+        )
+        .unwrap_err();
+    assert!(match e.kind() {
+        ErrorKind::InvalidInput => true,
+        _ => false,
+    });
+}
@@ -0,0 +1,19 @@
+//! Saving bitmap targets as files.
+
+#![cfg(feature = "png")]
+
+use piet::ErrorKind;
+use piet_common::Device;
+
+#[test]
+fn save_to_file_rejects_other_formats() {
+    let device = Device::new().unwrap();
+    let mut bitmap = device.bitmap_target(4, 2, 1.0).unwrap();
+    let path = std::env::temp_dir().join("piet-common-save-test.jpg");
+    let e = bitmap.save_to_file(&path).unwrap_err();
+    assert!(match e.kind() {
+        ErrorKind::NotSupported => true,
+        _ => false,
+    });
+    assert!(!path.exists());
+}
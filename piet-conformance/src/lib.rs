@@ -0,0 +1,132 @@
+//! Conformance tests for piet back-ends.
+//!
+//! Each [`Probe`] draws something small that exercises one behavior of the
+//! `RenderContext` contract, such as `restore` undoing a clip, and says what
+//! the result should be: either some sampled pixel colors or an error. A
+//! back-end runs the probes by implementing [`Backend`], usually in an
+//! integration test:
+//!
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     piet_conformance::assert_conformance(&mut MyBackend, &[]);
+//! }
+//! ```
+//!
+//! [`Probe`]: enum.Probe.html
+//! [`Backend`]: trait.Backend.html
+
+use std::fmt;
+
+use piet::Error;
+
+mod probe;
+
+pub use crate::probe::*;
+
+/// How far a sampled channel may be from the expected value.
+const CHANNEL_TOLERANCE: u8 = 8;
+
+/// Rendered pixels, in RGBA order with premultiplied alpha.
+pub struct Pixels {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Pixels {
+    /// The color of a pixel.
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let ix = (y * self.width + x) * 4;
+        let mut result = [0; 4];
+        result.copy_from_slice(&self.data[ix..ix + 4]);
+        result
+    }
+}
+
+/// A back-end under test.
+pub trait Backend {
+    /// Render a probe into a new target of the given size in pixels.
+    ///
+    /// Implementations create a render context without any scaling, call
+    /// [`Probe::draw`](enum.Probe.html#method.draw), then `finish` the
+    /// context, returning the first error from either.
+    fn render(&mut self, width: usize, height: usize, probe: Probe) -> Result<Pixels, Error>;
+}
+
+/// A probe that didn't produce the expected result.
+#[derive(Debug)]
+pub struct Failure {
+    pub probe: Probe,
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.probe, self.reason)
+    }
+}
+
+fn close(a: [u8; 4], b: [u8; 4]) -> bool {
+    (0..4).all(|i| (a[i] as i16 - b[i] as i16).abs() <= CHANNEL_TOLERANCE as i16)
+}
+
+/// Run a single probe.
+pub fn run(backend: &mut impl Backend, probe: Probe) -> Result<(), Failure> {
+    let fail = |reason: String| Err(Failure { probe, reason });
+    let result = backend.render(SIZE.0, SIZE.1, probe);
+    match (probe.expectation(), result) {
+        (Expectation::Pixels(samples), Ok(pixels)) => {
+            for &((x, y), expected) in samples {
+                let actual = pixels.get(x, y);
+                if !close(actual, expected) {
+                    return fail(format!(
+                        "pixel ({}, {}) is {:?}, expected {:?}",
+                        x, y, actual, expected
+                    ));
+                }
+            }
+            Ok(())
+        }
+        (Expectation::Pixels(_), Err(e)) => fail(format!("unexpected error: {}", e)),
+        (Expectation::Error(matches), Err(ref e)) if matches(e.kind()) => Ok(()),
+        (Expectation::Error(_), Err(e)) => fail(format!("wrong error: {:?}", e.kind())),
+        (Expectation::DeferredError(matches, op), Err(ref e))
+            if matches(e.kind()) && e.op() == Some(op) =>
+        {
+            Ok(())
+        }
+        (Expectation::DeferredError(..), Err(e)) => {
+            fail(format!("wrong error: {:?} from {:?}", e.kind(), e.op()))
+        }
+        (Expectation::Error(_), Ok(_)) | (Expectation::DeferredError(..), Ok(_)) => {
+            fail("expected an error, got none".to_owned())
+        }
+    }
+}
+
+/// Run all the probes, returning the failures.
+pub fn run_all(backend: &mut impl Backend) -> Vec<Failure> {
+    Probe::all()
+        .filter_map(|probe| run(backend, probe).err())
+        .collect()
+}
+
+/// Run all the probes, panicking unless exactly the known failures fail.
+///
+/// A known failure that passes is reported too, so the list can be kept
+/// up to date as the back-end improves.
+pub fn assert_conformance(backend: &mut impl Backend, known_failures: &[Probe]) {
+    let mut problems = Vec::new();
+    for probe in Probe::all() {
+        match (run(backend, probe), known_failures.contains(&probe)) {
+            (Err(failure), false) => problems.push(failure.to_string()),
+            (Ok(()), true) => problems.push(format!(
+                "{:?}: passes, but is listed as a known failure",
+                probe
+            )),
+            _ => (),
+        }
+    }
+    assert!(problems.is_empty(), "\n{}", problems.join("\n"));
+}
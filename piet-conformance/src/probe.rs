@@ -0,0 +1,476 @@
+//! The probes: small drawings exercising one behavior each.
+
+use kurbo::{Affine, BezPath, Line, Rect, Shape};
+
+use piet::{
+    DrawImageOptions, Error, ErrorKind, FillRule, Image, ImageFormat, InterpolationMode, Offscreen,
+    RenderContext, StrokeStyle,
+};
+
+/// Opaque red, used for everything the probes draw.
+pub const RED: [u8; 4] = [255, 0, 0, 255];
+/// Opaque white, the background of every probe.
+pub const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+/// The outcome a probe expects.
+pub enum Expectation {
+    /// Rendering succeeds, and the pixels at the given positions have the
+    /// given premultiplied RGBA colors.
+    Pixels(&'static [((usize, usize), [u8; 4])]),
+    /// Drawing or finishing the context fails with a matching error.
+    Error(fn(&ErrorKind) -> bool),
+    /// Drawing fails with a matching error, recorded by the named operation
+    /// and reported later by `status`.
+    DeferredError(fn(&ErrorKind) -> bool, &'static str),
+}
+
+fn is_stack_unbalance(kind: &ErrorKind) -> bool {
+    match kind {
+        ErrorKind::StackUnbalance => true,
+        _ => false,
+    }
+}
+
+fn is_invalid_input(kind: &ErrorKind) -> bool {
+    match kind {
+        ErrorKind::InvalidInput => true,
+        _ => false,
+    }
+}
+
+/// A conformance probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// A transform made after `save` doesn't outlive the `restore`.
+    RestoreUndoesTransform,
+    /// A clip made after `save` doesn't outlive the `restore`.
+    RestoreUndoesClip,
+    /// Drawing outside the clip has no effect.
+    ClipLimitsDrawing,
+    /// Clips accumulate by intersection.
+    ClipsIntersect,
+    /// `restore` without a matching `save` is a stack imbalance.
+    RestoreWithoutSave,
+    /// `finish` with saves left on the stack is a stack imbalance.
+    FinishWithUnbalancedSave,
+    /// `with_save` leaves the stack balanced.
+    WithSaveBalances,
+    /// Transforming a finished context records an error for `status`.
+    TransformAfterFinish,
+    /// An even-odd fill leaves a hole where shapes overlap.
+    EvenOddLeavesHoles,
+    /// A non-zero fill of the same shapes has no hole.
+    NonZeroFillsOverlap,
+    /// A dash pattern starts at the beginning of the path.
+    DashStartsOn,
+    /// The dash offset shifts the pattern along the path.
+    DashOffsetShifts,
+    /// Transforms apply to subsequent drawing.
+    TransformApplies,
+    /// `make_image` rejects a buffer shorter than the image.
+    ImageShortBuffer,
+    /// `make_image` rejects an image without pixels.
+    ImageEmpty,
+    /// `make_image_with_stride` skips the padding between rows.
+    ImageStride,
+    /// `BgraSeparate` images have their channels swapped into place.
+    ImageBgra,
+    /// `Rgba16Separate` images are dithered down without changing exact colors.
+    ImageRgba16,
+    /// `draw_image_area` doesn't filter in pixels from outside the area.
+    ImageAreaNoBleed,
+    /// `draw_image_area` draws nothing from or into a rectangle without
+    /// area, and drawing carries on afterwards.
+    ImageAreaDegenerate,
+    /// `draw_image_with_options` applies opacity and a transform.
+    ImageOptions,
+    /// The clip in `DrawImageOptions` limits the image.
+    ImageOptionsClip,
+    /// `DrawImageOptions` with a transform that has no inverse draw nothing,
+    /// and drawing carries on afterwards.
+    ImageOptionsSingular,
+    /// An offscreen target makes an image that can be drawn.
+    OffscreenImage,
+    /// `capture_image_area` copies what was drawn, ignoring the transform.
+    CaptureImage,
+    /// `update_image` replaces just the dirty area.
+    ImageUpdate,
+    /// `update_image` rejects a dirty area outside the image.
+    ImageUpdateOutside,
+    /// An image reports its size and format, and reads its pixels back.
+    ImageReadback,
+    /// `draw_mask` fills with the brush where an `Alpha8` mask is opaque.
+    MaskCoverage,
+}
+
+const PROBES: &[Probe] = &[
+    Probe::RestoreUndoesTransform,
+    Probe::RestoreUndoesClip,
+    Probe::ClipLimitsDrawing,
+    Probe::ClipsIntersect,
+    Probe::RestoreWithoutSave,
+    Probe::FinishWithUnbalancedSave,
+    Probe::WithSaveBalances,
+    Probe::TransformAfterFinish,
+    Probe::EvenOddLeavesHoles,
+    Probe::NonZeroFillsOverlap,
+    Probe::DashStartsOn,
+    Probe::DashOffsetShifts,
+    Probe::TransformApplies,
+    Probe::ImageShortBuffer,
+    Probe::ImageEmpty,
+    Probe::ImageStride,
+    Probe::ImageBgra,
+    Probe::ImageRgba16,
+    Probe::ImageAreaNoBleed,
+    Probe::ImageAreaDegenerate,
+    Probe::ImageOptions,
+    Probe::ImageOptionsClip,
+    Probe::ImageOptionsSingular,
+    Probe::OffscreenImage,
+    Probe::CaptureImage,
+    Probe::ImageUpdate,
+    Probe::ImageUpdateOutside,
+    Probe::ImageReadback,
+    Probe::MaskCoverage,
+];
+
+/// The size of every probe, in pixels.
+pub const SIZE: (usize, usize) = (40, 20);
+
+fn everything() -> Rect {
+    Rect::new(0.0, 0.0, SIZE.0 as f64, SIZE.1 as f64)
+}
+
+/// A square with a smaller square inside it, wound the same way.
+fn nested_squares() -> BezPath {
+    let mut path = BezPath::new();
+    for &(x0, x1) in &[(0.0, 20.0), (5.0, 15.0)] {
+        path.moveto((x0, x0));
+        path.lineto((x1, x0));
+        path.lineto((x1, x1));
+        path.lineto((x0, x1));
+        path.closepath();
+    }
+    path
+}
+
+/// A horizontal dashed line through the middle, 10 on and 10 off.
+fn dashed_line(rc: &mut impl RenderContext, offset: f64) -> Result<(), Error> {
+    let brush = rc.solid_brush(0xFF_00_00_FF)?;
+    let mut style = StrokeStyle::new();
+    style.set_dash(vec![10.0, 10.0], offset);
+    rc.stroke(
+        Line::new((0.0, 10.0), (40.0, 10.0)),
+        &brush,
+        4.0,
+        Some(&style),
+    );
+    Ok(())
+}
+
+impl Probe {
+    /// Iterate over all the probes.
+    pub fn all() -> impl Iterator<Item = Probe> {
+        PROBES.iter().cloned()
+    }
+
+    /// Draw the probe on a context of [`SIZE`](constant.SIZE.html).
+    ///
+    /// The back-end is expected to finish the context afterwards.
+    pub fn draw(self, rc: &mut impl RenderContext) -> Result<(), Error> {
+        rc.clear(0xFF_FF_FF);
+        let red = rc.solid_brush(0xFF_00_00_FF)?;
+        match self {
+            Probe::RestoreUndoesTransform => {
+                rc.save()?;
+                rc.transform(Affine::translate((20.0, 0.0)));
+                rc.restore()?;
+                rc.fill(Rect::new(0.0, 0.0, 10.0, 20.0), &red, FillRule::NonZero);
+            }
+            Probe::RestoreUndoesClip => {
+                rc.save()?;
+                rc.clip(Rect::new(0.0, 0.0, 10.0, 10.0), FillRule::NonZero);
+                rc.restore()?;
+                rc.fill(everything(), &red, FillRule::NonZero);
+            }
+            Probe::ClipLimitsDrawing => {
+                rc.clip(Rect::new(0.0, 0.0, 20.0, 20.0), FillRule::NonZero);
+                rc.fill(everything(), &red, FillRule::NonZero);
+            }
+            Probe::ClipsIntersect => {
+                rc.clip(Rect::new(0.0, 0.0, 20.0, 20.0), FillRule::NonZero);
+                rc.clip(Rect::new(10.0, 0.0, 40.0, 20.0), FillRule::NonZero);
+                rc.fill(everything(), &red, FillRule::NonZero);
+            }
+            Probe::RestoreWithoutSave => rc.restore()?,
+            Probe::FinishWithUnbalancedSave => rc.save()?,
+            Probe::WithSaveBalances => {
+                rc.with_save(|rc| {
+                    rc.transform(Affine::translate((20.0, 0.0)));
+                    Ok(())
+                })?;
+                rc.fill(Rect::new(0.0, 0.0, 10.0, 20.0), &red, FillRule::NonZero);
+            }
+            Probe::TransformAfterFinish => {
+                rc.finish()?;
+                rc.transform(Affine::translate((20.0, 0.0)));
+                rc.status()?;
+            }
+            Probe::EvenOddLeavesHoles => rc.fill(nested_squares(), &red, FillRule::EvenOdd),
+            Probe::NonZeroFillsOverlap => rc.fill(nested_squares(), &red, FillRule::NonZero),
+            Probe::DashStartsOn => dashed_line(rc, 0.0)?,
+            Probe::DashOffsetShifts => dashed_line(rc, 5.0)?,
+            Probe::TransformApplies => {
+                rc.transform(Affine::translate((20.0, 0.0)));
+                rc.fill(Rect::new(0.0, 0.0, 10.0, 20.0), &red, FillRule::NonZero);
+            }
+            Probe::ImageShortBuffer => {
+                rc.make_image(4, 4, &[0; 4 * 4 * 4 - 1], ImageFormat::RgbaSeparate)?;
+            }
+            Probe::ImageEmpty => {
+                rc.make_image(0, 4, &[], ImageFormat::RgbaSeparate)?;
+            }
+            Probe::ImageStride => {
+                // A 2x2 red image, with rows padded by a white pixel that
+                // must not be drawn.
+                let mut buf = Vec::new();
+                for _ in 0..2 {
+                    buf.extend_from_slice(&RED);
+                    buf.extend_from_slice(&RED);
+                    buf.extend_from_slice(&WHITE);
+                }
+                let image = rc.make_image_with_stride(2, 2, 12, &buf, ImageFormat::RgbaSeparate)?;
+                rc.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::ImageBgra => {
+                let image = rc.make_image(1, 1, &[0, 0, 255, 255], ImageFormat::BgraSeparate)?;
+                rc.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::ImageRgba16 => {
+                let red = [255, 255, 0, 0, 0, 0, 255, 255];
+                let image = rc.make_image(1, 1, &red, ImageFormat::Rgba16Separate)?;
+                rc.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::ImageAreaNoBleed => {
+                // A red pixel next to a black one, which must not show.
+                let buf = [255, 0, 0, 255, 0, 0, 0, 255];
+                let image = rc.make_image(2, 1, &buf, ImageFormat::RgbaSeparate)?;
+                rc.draw_image_area(
+                    &image,
+                    Rect::new(0.0, 0.0, 1.0, 1.0),
+                    everything(),
+                    InterpolationMode::Bilinear,
+                );
+            }
+            Probe::ImageAreaDegenerate => {
+                let image = rc.make_image(1, 1, &RED, ImageFormat::RgbaSeparate)?;
+                let whole = Rect::new(0.0, 0.0, 1.0, 1.0);
+                rc.draw_image_area(
+                    &image,
+                    Rect::new(0.5, 0.0, 0.5, 1.0),
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::Bilinear,
+                );
+                rc.draw_image_area(
+                    &image,
+                    whole,
+                    Rect::new(10.0, 0.0, 10.0, 20.0),
+                    InterpolationMode::Bilinear,
+                );
+                rc.draw_image_area(
+                    &image,
+                    whole,
+                    Rect::new(20.0, 0.0, 40.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::ImageOptions => {
+                let image = rc.make_image(1, 1, &RED, ImageFormat::RgbaSeparate)?;
+                let mut options = DrawImageOptions::new();
+                options.set_opacity(0.5);
+                options.set_transform(Affine::translate((20.0, 0.0)));
+                rc.draw_image_with_options(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                    &options,
+                );
+            }
+            Probe::ImageOptionsClip => {
+                let image = rc.make_image(1, 1, &RED, ImageFormat::RgbaSeparate)?;
+                let mut options = DrawImageOptions::new();
+                options.set_clip(Rect::new(0.0, 0.0, 20.0, 20.0).into_bez_path(1e-3));
+                rc.draw_image_with_options(
+                    &image,
+                    everything(),
+                    InterpolationMode::NearestNeighbor,
+                    &options,
+                );
+            }
+            Probe::ImageOptionsSingular => {
+                let image = rc.make_image(1, 1, &RED, ImageFormat::RgbaSeparate)?;
+                let left = Rect::new(0.0, 0.0, 20.0, 20.0);
+                let singular = [
+                    Affine::scale(0.0),
+                    Affine::new([1.0, 2.0, 2.0, 4.0, 0.0, 0.0]),
+                    Affine::translate((std::f64::NAN, 0.0)),
+                    Affine::scale(std::f64::INFINITY),
+                ];
+                for &transform in &singular {
+                    let mut options = DrawImageOptions::new();
+                    options.set_transform(transform);
+                    rc.draw_image_with_options(&image, left, InterpolationMode::Bilinear, &options);
+                }
+                rc.draw_image(
+                    &image,
+                    Rect::new(20.0, 0.0, 40.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::OffscreenImage => {
+                let mut offscreen = rc.create_offscreen(10, 10)?;
+                {
+                    let orc = offscreen.render_context();
+                    let red = orc.solid_brush(0xFF_00_00_FF)?;
+                    orc.fill(Rect::new(0.0, 0.0, 10.0, 10.0), &red, FillRule::NonZero);
+                }
+                let image = offscreen.into_image()?;
+                rc.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::CaptureImage => {
+                let red = rc.solid_brush(0xFF_00_00_FF)?;
+                rc.fill(Rect::new(0.0, 0.0, 20.0, 20.0), &red, FillRule::NonZero);
+                rc.transform(Affine::translate((20.0, 0.0)));
+                let image = rc.capture_image_area(Rect::new(0.0, 0.0, 20.0, 20.0))?;
+                rc.draw_image(
+                    &image,
+                    Rect::new(0.0, 0.0, 20.0, 20.0),
+                    InterpolationMode::NearestNeighbor,
+                );
+            }
+            Probe::ImageUpdate => {
+                let mut image = rc.make_image(2, 1, &[255; 8], ImageFormat::RgbaSeparate)?;
+                rc.update_image(
+                    &mut image,
+                    Rect::new(1.0, 0.0, 2.0, 1.0),
+                    &RED,
+                    ImageFormat::RgbaSeparate,
+                )?;
+                rc.draw_image(&image, everything(), InterpolationMode::NearestNeighbor);
+            }
+            Probe::ImageUpdateOutside => {
+                let mut image = rc.make_image(2, 1, &[255; 8], ImageFormat::RgbaSeparate)?;
+                rc.update_image(
+                    &mut image,
+                    Rect::new(1.0, 0.0, 3.0, 1.0),
+                    &[255; 8],
+                    ImageFormat::RgbaSeparate,
+                )?;
+            }
+            Probe::ImageReadback => {
+                let bgra = [0, 0, 255, 255, 255, 255, 255, 255];
+                let image = rc.make_image(2, 1, &bgra, ImageFormat::BgraSeparate)?;
+                // A wrong size or format leaves the probe blank, so it fails.
+                if image.size() == (2, 1) && image.format() == ImageFormat::BgraSeparate {
+                    let rgba = image.to_raw_pixels(ImageFormat::RgbaSeparate)?;
+                    let image = rc.make_image(2, 1, &rgba, ImageFormat::RgbaSeparate)?;
+                    rc.draw_image(&image, everything(), InterpolationMode::NearestNeighbor);
+                }
+            }
+            Probe::MaskCoverage => {
+                let red = rc.solid_brush(0xFF_00_00_FF)?;
+                let mask = rc.make_image(4, 1, &[255, 255, 0, 0], ImageFormat::Alpha8)?;
+                rc.draw_mask(&mask, everything(), &red);
+            }
+        }
+        Ok(())
+    }
+
+    /// What rendering the probe should produce.
+    ///
+    /// Sample positions are kept away from edges, so anti-aliasing doesn't
+    /// affect them.
+    pub fn expectation(self) -> Expectation {
+        match self {
+            Probe::RestoreUndoesTransform | Probe::WithSaveBalances => {
+                Expectation::Pixels(&[((5, 10), RED), ((25, 10), WHITE)])
+            }
+            Probe::RestoreUndoesClip => Expectation::Pixels(&[((5, 5), RED), ((30, 15), RED)]),
+            Probe::ClipLimitsDrawing => Expectation::Pixels(&[((10, 10), RED), ((30, 10), WHITE)]),
+            Probe::ClipsIntersect => {
+                Expectation::Pixels(&[((5, 10), WHITE), ((15, 10), RED), ((30, 10), WHITE)])
+            }
+            Probe::RestoreWithoutSave | Probe::FinishWithUnbalancedSave => {
+                Expectation::Error(is_stack_unbalance)
+            }
+            Probe::TransformAfterFinish => {
+                Expectation::DeferredError(is_invalid_input, "transform")
+            }
+            Probe::EvenOddLeavesHoles => {
+                Expectation::Pixels(&[((2, 10), RED), ((10, 10), WHITE), ((30, 10), WHITE)])
+            }
+            Probe::NonZeroFillsOverlap => {
+                Expectation::Pixels(&[((2, 10), RED), ((10, 10), RED), ((30, 10), WHITE)])
+            }
+            Probe::DashStartsOn => Expectation::Pixels(&[
+                ((5, 10), RED),
+                ((15, 10), WHITE),
+                ((25, 10), RED),
+                ((10, 2), WHITE),
+            ]),
+            Probe::DashOffsetShifts => Expectation::Pixels(&[
+                ((2, 10), RED),
+                ((10, 10), WHITE),
+                ((20, 10), RED),
+                ((30, 10), WHITE),
+            ]),
+            Probe::TransformApplies => Expectation::Pixels(&[((5, 10), WHITE), ((25, 10), RED)]),
+            Probe::ImageShortBuffer | Probe::ImageEmpty => Expectation::Error(is_invalid_input),
+            Probe::ImageUpdateOutside => Expectation::Error(is_invalid_input),
+            Probe::ImageStride => Expectation::Pixels(&[
+                ((5, 5), RED),
+                ((15, 5), RED),
+                ((5, 15), RED),
+                ((15, 15), RED),
+                ((30, 10), WHITE),
+            ]),
+            Probe::ImageBgra
+            | Probe::ImageRgba16
+            | Probe::OffscreenImage
+            | Probe::ImageReadback => Expectation::Pixels(&[((10, 10), RED), ((30, 10), WHITE)]),
+            Probe::ImageAreaNoBleed => {
+                Expectation::Pixels(&[((2, 10), RED), ((20, 10), RED), ((38, 10), RED)])
+            }
+            Probe::ImageAreaDegenerate => {
+                Expectation::Pixels(&[((5, 10), WHITE), ((10, 10), WHITE), ((30, 10), RED)])
+            }
+            Probe::ImageOptions => {
+                Expectation::Pixels(&[((10, 10), WHITE), ((30, 10), [255, 128, 128, 255])])
+            }
+            Probe::ImageOptionsClip => Expectation::Pixels(&[((10, 10), RED), ((30, 10), WHITE)]),
+            Probe::ImageOptionsSingular => {
+                Expectation::Pixels(&[((10, 10), WHITE), ((30, 10), RED)])
+            }
+            Probe::CaptureImage => Expectation::Pixels(&[((10, 10), RED), ((30, 10), RED)]),
+            Probe::ImageUpdate => Expectation::Pixels(&[((10, 10), WHITE), ((30, 10), RED)]),
+            Probe::MaskCoverage => Expectation::Pixels(&[((5, 10), RED), ((35, 10), WHITE)]),
+        }
+    }
+}
@@ -4,7 +4,9 @@ use direct2d::math::{ColorF, Matrix3x2F, Point2F, RectF};
 
 use kurbo::{Affine, Rect, Vec2};
 
-use piet::{Error, GradientStop, LineCap, LineJoin, RoundFrom, RoundInto, StrokeStyle};
+use piet::{
+    Error, GradientStop, InterpolationMode, LineCap, LineJoin, RoundFrom, RoundInto, StrokeStyle,
+};
 
 use crate::error::WrapError;
 
@@ -94,6 +96,22 @@ pub(crate) fn gradient_stop_to_d2d(stop: &GradientStop) -> direct2d::brush::grad
     }
 }
 
+pub(crate) fn convert_interp(
+    interp: InterpolationMode,
+) -> direct2d::enums::BitmapInterpolationMode {
+    match interp {
+        InterpolationMode::NearestNeighbor => {
+            direct2d::enums::BitmapInterpolationMode::NearestNeighbor
+        }
+        // Render targets only have these two modes. High quality drawing
+        // samples a mip level of the image when scaling down.
+        // TODO: cubic interpolation needs a device context.
+        InterpolationMode::Bilinear
+        | InterpolationMode::Bicubic
+        | InterpolationMode::HighQuality => direct2d::enums::BitmapInterpolationMode::Linear,
+    }
+}
+
 fn convert_line_cap(line_cap: LineCap) -> direct2d::enums::CapStyle {
     match line_cap {
         LineCap::Butt => direct2d::enums::CapStyle::Flat,
@@ -40,7 +40,7 @@ pub trait WrapError<T> {
 impl<T> WrapError<T> for Result<T, direct2d::Error> {
     fn wrap(self) -> Result<T, Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedD2DError(e));
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedD2DError(e));
             e.into()
         })
     }
@@ -49,7 +49,7 @@ impl<T> WrapError<T> for Result<T, direct2d::Error> {
 impl<T> WrapError<T> for Result<T, DWriteError> {
     fn wrap(self) -> Result<T, Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedDWriteError(e));
+            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(WrappedDWriteError(e));
             e.into()
         })
     }
@@ -4,12 +4,14 @@ mod conv;
 pub mod error;
 
 use crate::conv::{
-    affine_to_matrix3x2f, convert_stroke_style, gradient_stop_to_d2d, rect_to_rectf,
-    rgba_to_colorf, to_point2f, Point2,
+    affine_to_matrix3x2f, convert_interp, convert_stroke_style, gradient_stop_to_d2d,
+    rect_to_rectf, rgba_to_colorf, to_point2f, Point2,
 };
 use crate::error::WrapError;
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use winapi::shared::basetsd::UINT32;
 use winapi::um::dcommon::D2D_SIZE_U;
@@ -19,9 +21,7 @@ use dxgi::Format;
 use direct2d::brush::gradient::linear::LinearGradientBrushBuilder;
 use direct2d::brush::gradient::radial::RadialGradientBrushBuilder;
 use direct2d::brush::{Brush, GenericBrush, SolidColorBrush};
-use direct2d::enums::{
-    AlphaMode, BitmapInterpolationMode, DrawTextOptions, FigureBegin, FigureEnd, FillMode,
-};
+use direct2d::enums::{AlphaMode, DrawTextOptions, FigureBegin, FigureEnd, FillMode};
 use direct2d::geometry::path::{FigureBuilder, GeometryBuilder};
 use direct2d::geometry::Path;
 use direct2d::image::Bitmap;
@@ -36,8 +36,11 @@ use directwrite::TextFormat;
 use kurbo::{Affine, PathEl, Rect, Shape};
 
 use piet::{
-    new_error, Error, ErrorKind, FillRule, Font, FontBuilder, Gradient, ImageFormat,
-    InterpolationMode, RenderContext, RoundInto, StrokeStyle, Text, TextLayout, TextLayoutBuilder,
+    affine_inverse, check_image_buf, convert_from_rgba_premul, convert_to_rgba_premul,
+    dither_to_rgba_separate, halve_image_buf, image_dirty_area, image_src_area, is_drawable_rect,
+    mip_level, new_error, packed_image_buf, premul, DrawImageOptions, Error, ErrorKind, FillRule,
+    Font, FontBuilder, Gradient, Image, ImageFormat, InterpolationMode, Offscreen, RenderContext,
+    RoundInto, StrokeStyle, Text, TextLayout, TextLayoutBuilder,
 };
 
 pub struct D2DRenderContext<'a> {
@@ -50,9 +53,43 @@ pub struct D2DRenderContext<'a> {
     /// The context state stack. There is always at least one, until finishing.
     ctx_stack: Vec<CtxState>,
 
+    /// The first error from an operation that can't return one, reported
+    /// by `status` and `finish`.
     err: Result<(), Error>,
 }
 
+/// An image, backed by a Direct2D bitmap.
+///
+/// The image keeps a copy of its premultiplied pixels, which updates,
+/// pixel readback and mip levels are made from, so nothing is read back
+/// from the GPU.
+pub struct D2DImage {
+    bitmap: Bitmap,
+    width: usize,
+    height: usize,
+    format: ImageFormat,
+    /// The premultiplied pixels, in the bitmap's order.
+    pixels: Vec<u8>,
+    pixel_format: Format,
+    alpha_mode: AlphaMode,
+    /// The image halved again and again, down to a single pixel, built the
+    /// first time they're needed.
+    ///
+    /// Render targets can only filter bilinearly, so these are sampled
+    /// instead when drawing with `HighQuality` scales the image down.
+    mips: RefCell<Option<Rc<Vec<Mip>>>>,
+}
+
+/// A mip level of an image.
+struct Mip {
+    bitmap: Bitmap,
+    width: usize,
+    height: usize,
+}
+
+/// Offscreen targets aren't supported yet, so this can't be created.
+pub enum D2DOffscreen {}
+
 pub struct D2DText<'a> {
     dwrite: &'a directwrite::Factory,
 }
@@ -103,7 +140,8 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
     }
 
     fn current_transform(&self) -> Affine {
-        // This is an unwrap because we protect the invariant.
+        // This is an unwrap because we protect the invariant, and callers
+        // check that the context hasn't been finished.
         self.ctx_stack.last().unwrap().transform
     }
 
@@ -114,6 +152,49 @@ impl<'b, 'a: 'b> D2DRenderContext<'a> {
             self.rt.pop_layer();
         }
     }
+
+    /// Record an error from the named operation, unless one is already pending.
+    fn record_err(&mut self, op: &'static str, e: Error) {
+        if self.err.is_ok() {
+            self.err = Err(e.with_op(op));
+        }
+    }
+
+    /// Draw an image with options, recording errors for the named operation.
+    fn draw_image_for(
+        &mut self,
+        op: &'static str,
+        image: &D2DImage,
+        rect: Rect,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        // Nothing is drawn through a transform without an inverse, or into a
+        // rectangle without area.
+        if affine_inverse(options.transform()).is_none() || !is_drawable_rect(rect) {
+            return;
+        }
+        let result = self.with_save(|rc| {
+            if let Some(clip) = options.clip() {
+                rc.clip(clip.clone(), FillRule::NonZero);
+            }
+            rc.transform(options.transform());
+            let src_rect = Rect::new(0.0, 0.0, image.width as f64, image.height as f64);
+            let (bitmap, _, _, src_rect) =
+                image.source(&rc.rt, interp, rc.current_transform(), src_rect, rect)?;
+            rc.rt.draw_bitmap(
+                &bitmap,
+                rect_to_rectf(rect),
+                options.opacity() as f32,
+                convert_interp(interp),
+                rect_to_rectf(src_rect),
+            );
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.record_err(op, e);
+        }
+    }
 }
 
 enum PathBuilder<'a> {
@@ -204,7 +285,8 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
 
     type TextLayout = D2DTextLayout;
 
-    type Image = Bitmap;
+    type Image = D2DImage;
+    type Offscreen = D2DOffscreen;
 
     fn status(&mut self) -> Result<(), Error> {
         std::mem::replace(&mut self.err, Ok(()))
@@ -257,7 +339,7 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         // TODO: various special-case shapes, for efficiency
         match path_from_shape(self.factory, true, shape, fill_rule) {
             Ok(path) => self.rt.fill_geometry(&path, brush),
-            Err(e) => self.err = Err(e),
+            Err(e) => self.record_err("fill", e),
         }
     }
 
@@ -272,32 +354,42 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         let path = match path_from_shape(self.factory, false, shape, FillRule::EvenOdd) {
             Ok(path) => path,
             Err(e) => {
-                self.err = Err(e);
+                self.record_err("stroke", e);
                 return;
             }
         };
         let width = width.round_into();
-        let style = if let Some(style) = style {
-            Some(convert_stroke_style(self.factory, style, width).expect("TODO"))
-        } else {
-            None
+        let style = match style {
+            Some(style) => match convert_stroke_style(self.factory, style, width) {
+                Ok(style) => Some(style),
+                Err(e) => {
+                    self.record_err("stroke", e);
+                    return;
+                }
+            },
+            None => None,
         };
         self.rt.draw_geometry(&path, brush, width, style.as_ref());
     }
 
     fn clip(&mut self, shape: impl Shape, fill_rule: FillRule) {
+        if self.ctx_stack.is_empty() {
+            // The context has already been finished.
+            self.record_err("clip", new_error(ErrorKind::InvalidInput));
+            return;
+        }
         // TODO: set size based on bbox of shape.
         let layer = match Layer::create(&mut self.rt, None).wrap() {
             Ok(layer) => layer,
             Err(e) => {
-                self.err = Err(e);
+                self.record_err("clip", e);
                 return;
             }
         };
         let path = match path_from_shape(self.factory, false, shape, fill_rule) {
             Ok(path) => path,
             Err(e) => {
-                self.err = Err(e);
+                self.record_err("clip", e);
                 return;
             }
         };
@@ -310,6 +402,7 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
             .with_mask(path)
             .with_mask_transform(transform)
             .push();
+        // This is an unwrap because we checked the stack above.
         self.ctx_stack.last_mut().unwrap().n_layers_pop += 1;
     }
 
@@ -340,6 +433,10 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn save(&mut self) -> Result<(), Error> {
+        if self.ctx_stack.is_empty() {
+            // The context has already been finished.
+            return Err(new_error(ErrorKind::InvalidInput));
+        }
         let new_state = CtxState {
             transform: self.current_transform(),
             n_layers_pop: 0,
@@ -371,24 +468,47 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
     }
 
     fn transform(&mut self, transform: Affine) {
-        self.ctx_stack.last_mut().unwrap().transform *= transform;
+        match self.ctx_stack.last_mut() {
+            Some(state) => state.transform *= transform,
+            None => {
+                // The context has already been finished.
+                self.record_err("transform", new_error(ErrorKind::InvalidInput));
+                return;
+            }
+        }
         self.rt
             .set_transform(&affine_to_matrix3x2f(self.current_transform()));
     }
 
-    fn make_image(
+    fn make_image_with_stride(
         &mut self,
         width: usize,
         height: usize,
+        stride: usize,
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
-        // TODO: this method _really_ needs error checking, so much can go wrong...
+        if format.bits_per_channel() > 8 {
+            // Images are stored with 8 bits per channel, so dither down.
+            let buf = dither_to_rgba_separate(width, height, stride, buf, format)?;
+            return self.make_image(width, height, &buf, ImageFormat::RgbaSeparate);
+        }
         let alpha_mode = match format {
-            ImageFormat::Rgb => AlphaMode::Ignore,
-            ImageFormat::RgbaPremul | ImageFormat::RgbaSeparate => AlphaMode::Premultiplied,
+            ImageFormat::Rgb | ImageFormat::Grayscale => AlphaMode::Ignore,
+            ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::BgraPremul
+            | ImageFormat::BgraSeparate
+            | ImageFormat::Alpha8 => AlphaMode::Premultiplied,
             _ => return Err(new_error(ErrorKind::NotSupported)),
         };
+        // BGRA is Direct2D's native order; everything else goes through RGBA.
+        let pixel_format = match format {
+            ImageFormat::BgraPremul | ImageFormat::BgraSeparate => Format::B8G8R8A8Unorm,
+            _ => Format::R8G8B8A8Unorm,
+        };
+        check_image_buf(width, height, stride, buf, format)?;
+        let buf = packed_image_buf(width, height, stride, buf, format);
         let buf = match format {
             ImageFormat::Rgb => {
                 let mut new_buf = vec![255; width * height * 4];
@@ -399,13 +519,9 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 }
                 Cow::from(new_buf)
             }
-            ImageFormat::RgbaSeparate => {
+            ImageFormat::RgbaSeparate | ImageFormat::BgraSeparate => {
                 let mut new_buf = vec![255; width * height * 4];
                 // TODO (performance): this would be soooo much faster with SIMD
-                fn premul(x: u8, a: u8) -> u8 {
-                    let y = (x as u16) * (a as u16);
-                    ((y + (y >> 8) + 0x80) >> 8) as u8
-                }
                 for i in 0..width * height {
                     let a = buf[i * 4 + 3];
                     new_buf[i * 4 + 0] = premul(buf[i * 4 + 0], a);
@@ -415,23 +531,73 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
                 }
                 Cow::from(new_buf)
             }
-            ImageFormat::RgbaPremul => Cow::from(buf),
+            ImageFormat::Grayscale | ImageFormat::Alpha8 => {
+                let mut new_buf = vec![0; width * height * 4];
+                convert_to_rgba_premul(&buf, &mut new_buf, format)?;
+                Cow::from(new_buf)
+            }
+            ImageFormat::RgbaPremul | ImageFormat::BgraPremul => buf,
             // This should be unreachable, we caught it above.
             _ => return Err(new_error(ErrorKind::NotSupported)),
         };
-        Bitmap::create(&self.rt)
-            .with_raw_data(
-                SizeU(D2D_SIZE_U {
-                    width: width as UINT32,
-                    height: height as UINT32,
-                }),
-                &buf,
-                width as UINT32 * 4,
-            )
-            .with_format(Format::R8G8B8A8Unorm)
-            .with_alpha_mode(alpha_mode)
-            .build()
-            .wrap()
+        let pixels = buf.into_owned();
+        let bitmap = create_bitmap(&self.rt, width, height, &pixels, pixel_format, alpha_mode)?;
+        Ok(D2DImage {
+            bitmap,
+            width,
+            height,
+            format,
+            pixels,
+            pixel_format,
+            alpha_mode,
+            mips: RefCell::new(None),
+        })
+    }
+
+    fn update_image(
+        &mut self,
+        image: &mut Self::Image,
+        dirty_rect: impl Into<Rect>,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let area = image_dirty_area(dirty_rect.into(), image.width, image.height)?;
+        let (width, height) = (area.width() as usize, area.height() as usize);
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let src_stride = width * format.bytes_per_pixel();
+        if format.bits_per_channel() > 8 {
+            let buf = dither_to_rgba_separate(width, height, src_stride, buf, format)?;
+            return self.update_image(image, area, &buf, ImageFormat::RgbaSeparate);
+        }
+        check_image_buf(width, height, src_stride, buf, format)?;
+        let mut rgba = vec![0; width * height * 4];
+        convert_to_rgba_premul(&buf[..src_stride * height], &mut rgba, format)?;
+        if let AlphaMode::Ignore = image.alpha_mode {
+            // Keep the pixels the bitmap shows, which is them over black.
+            for p in rgba.chunks_mut(4) {
+                p[3] = 255;
+            }
+        }
+        // TODO: use CopyFromMemory once the direct2d crate exposes it,
+        // rather than making a new bitmap.
+        let pixels_format = image.pixels_format();
+        for (y, row) in rgba.chunks(width * 4).enumerate() {
+            let dst_off = ((area.y0 as usize + y) * image.width + area.x0 as usize) * 4;
+            let dst = &mut image.pixels[dst_off..dst_off + width * 4];
+            convert_from_rgba_premul(row, dst, pixels_format, 0x00_00_00)?;
+        }
+        image.bitmap = create_bitmap(
+            &self.rt,
+            image.width,
+            image.height,
+            &image.pixels,
+            image.pixel_format,
+            image.alpha_mode,
+        )?;
+        *image.mips.get_mut() = None;
+        Ok(())
     }
 
     fn draw_image(
@@ -440,14 +606,249 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
-        let interp = match interp {
-            InterpolationMode::NearestNeighbor => BitmapInterpolationMode::NearestNeighbor,
-            InterpolationMode::Bilinear => BitmapInterpolationMode::Linear,
+        self.draw_image_for(
+            "draw_image",
+            image,
+            rect.into(),
+            interp,
+            &DrawImageOptions::new(),
+        );
+    }
+
+    fn draw_image_with_options(
+        &mut self,
+        image: &Self::Image,
+        rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        self.draw_image_for(
+            "draw_image_with_options",
+            image,
+            rect.into(),
+            interp,
+            options,
+        );
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let transform = match self.ctx_stack.last() {
+            Some(state) => state.transform,
+            None => {
+                // The context has already been finished.
+                self.record_err("draw_image_area", new_error(ErrorKind::InvalidInput));
+                return;
+            }
         };
-        let src_size = image.get_size();
-        let src_rect = (0.0, 0.0, src_size.0.width, src_size.0.height);
-        self.rt
-            .draw_bitmap(&image, rect_to_rectf(rect.into()), 1.0, interp, src_rect);
+        let dst_rect = dst_rect.into();
+        if !is_drawable_rect(dst_rect) {
+            return;
+        }
+        // At a mip level, the area's edge pixels may average in a little of
+        // their neighbors.
+        let (bitmap, width, height, src_rect) =
+            match image.source(&self.rt, interp, transform, src_rect.into(), dst_rect) {
+                Ok(source) => source,
+                Err(e) => {
+                    self.record_err("draw_image_area", e);
+                    return;
+                }
+            };
+        let area = match image_src_area(src_rect, width, height) {
+            Some(area) => area,
+            None => return,
+        };
+        // Linear filtering can reach half a pixel past the source rectangle,
+        // so inset it towards the centers of the area's edge pixels. That
+        // clamps sampling to the area, at the cost of a slight stretch. An
+        // area a single pixel across can't be inset without vanishing.
+        let inset = |size: f64| match interp {
+            InterpolationMode::NearestNeighbor => 0.0,
+            _ => ((size - 1.0) / 2.0).min(0.5),
+        };
+        let (inset_x, inset_y) = (inset(area.width()), inset(area.height()));
+        let src_rect = (
+            src_rect.x0.max(area.x0 + inset_x) as f32,
+            src_rect.y0.max(area.y0 + inset_y) as f32,
+            src_rect.x1.min(area.x1 - inset_x) as f32,
+            src_rect.y1.min(area.y1 - inset_y) as f32,
+        );
+        self.rt.draw_bitmap(
+            &bitmap,
+            rect_to_rectf(dst_rect),
+            1.0,
+            convert_interp(interp),
+            src_rect,
+        );
+    }
+
+    fn draw_mask(&mut self, _mask: &Self::Image, _rect: impl Into<Rect>, _brush: &Self::Brush) {
+        // TODO: use FillOpacityMask once the direct2d crate exposes it.
+        self.record_err("draw_mask", new_error(ErrorKind::NotSupported));
+    }
+
+    fn create_offscreen(
+        &mut self,
+        _width: usize,
+        _height: usize,
+    ) -> Result<Self::Offscreen, Error> {
+        // TODO: draw into a compatible render target.
+        Err(new_error(ErrorKind::NotSupported))
+    }
+
+    fn capture_image_area(&mut self, _rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        // TODO: use CopyFromRenderTarget once the direct2d crate exposes it.
+        Err(new_error(ErrorKind::NotSupported))
+    }
+}
+
+impl D2DImage {
+    /// The bitmap holding the full size pixels.
+    pub fn bitmap(&self) -> &Bitmap {
+        &self.bitmap
+    }
+
+    /// The bitmap to draw part of the image from, with its size, and the
+    /// part in its pixels.
+    ///
+    /// This is a mip level when drawing with `HighQuality` scales the image
+    /// down.
+    fn source(
+        &self,
+        rt: &GenericRenderTarget,
+        interp: InterpolationMode,
+        transform: Affine,
+        src_rect: Rect,
+        dst_rect: Rect,
+    ) -> Result<(Bitmap, usize, usize, Rect), Error> {
+        let level = match interp {
+            InterpolationMode::HighQuality => mip_level(transform, src_rect, dst_rect),
+            _ => 0,
+        };
+        if level == 0 {
+            return Ok((self.bitmap.clone(), self.width, self.height, src_rect));
+        }
+        let mips = self.mips(rt)?;
+        let level = level.min(mips.len());
+        if level == 0 {
+            return Ok((self.bitmap.clone(), self.width, self.height, src_rect));
+        }
+        let mip = &mips[level - 1];
+        let k = 1.0 / (1u64 << level) as f64;
+        let src_rect = Rect::new(
+            src_rect.x0 * k,
+            src_rect.y0 * k,
+            src_rect.x1 * k,
+            src_rect.y1 * k,
+        );
+        Ok((mip.bitmap.clone(), mip.width, mip.height, src_rect))
+    }
+
+    /// The format of the retained pixels.
+    fn pixels_format(&self) -> ImageFormat {
+        match self.pixel_format {
+            Format::B8G8R8A8Unorm => ImageFormat::BgraPremul,
+            _ => ImageFormat::RgbaPremul,
+        }
+    }
+
+    /// The mip levels, building them if this is the first time.
+    fn mips(&self, rt: &GenericRenderTarget) -> Result<Rc<Vec<Mip>>, Error> {
+        if let Some(ref mips) = *self.mips.borrow() {
+            return Ok(mips.clone());
+        }
+        let mut mips = Vec::new();
+        let (mut mip_width, mut mip_height) = (self.width, self.height);
+        let mut mip_buf = Cow::from(&self.pixels[..]);
+        while mip_width > 1 || mip_height > 1 {
+            let (half_width, half_height, half) = halve_image_buf(mip_width, mip_height, &mip_buf);
+            mips.push(Mip {
+                bitmap: create_bitmap(
+                    rt,
+                    half_width,
+                    half_height,
+                    &half,
+                    self.pixel_format,
+                    self.alpha_mode,
+                )?,
+                width: half_width,
+                height: half_height,
+            });
+            mip_width = half_width;
+            mip_height = half_height;
+            mip_buf = Cow::from(half);
+        }
+        let mips = Rc::new(mips);
+        *self.mips.borrow_mut() = Some(mips.clone());
+        Ok(mips)
+    }
+}
+
+/// Make a bitmap from packed, premultiplied pixels.
+fn create_bitmap(
+    rt: &GenericRenderTarget,
+    width: usize,
+    height: usize,
+    buf: &[u8],
+    pixel_format: Format,
+    alpha_mode: AlphaMode,
+) -> Result<Bitmap, Error> {
+    Bitmap::create(rt)
+        .with_raw_data(
+            SizeU(D2D_SIZE_U {
+                width: width as UINT32,
+                height: height as UINT32,
+            }),
+            buf,
+            width as UINT32 * 4,
+        )
+        .with_format(pixel_format)
+        .with_alpha_mode(alpha_mode)
+        .build()
+        .wrap()
+}
+
+impl Image for D2DImage {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    fn to_raw_pixels(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let mut rgba = vec![0; self.pixels.len()];
+        convert_to_rgba_premul(&self.pixels, &mut rgba, self.pixels_format())?;
+        if format == ImageFormat::RgbaPremul {
+            return Ok(rgba);
+        }
+        let mut buf = vec![0; self.width * self.height * format.bytes_per_pixel()];
+        // As documented on Image, formats without alpha go over black.
+        convert_from_rgba_premul(&rgba, &mut buf, format, 0x00_00_00)?;
+        Ok(buf)
+    }
+}
+
+impl Offscreen for D2DOffscreen {
+    type Image = D2DImage;
+    type Context = D2DRenderContext<'static>;
+
+    fn render_context(&mut self) -> &mut Self::Context {
+        match *self {}
+    }
+
+    fn into_image(self) -> Result<Self::Image, Error> {
+        match self {}
     }
 }
 
@@ -490,9 +891,11 @@ impl<'a> FontBuilder for D2DFontBuilder<'a> {
     type Out = D2DFont;
 
     fn build(self) -> Result<Self::Out, Error> {
-        Ok(D2DFont(
-            self.builder.with_family(&self.name).build().wrap()?,
-        ))
+        let name = self.name;
+        let format = self.builder.with_family(&name).build().map_err(|e| {
+            new_error(ErrorKind::FontLoadingFailed).with_context(format!("{}: {}", name, e))
+        })?;
+        Ok(D2DFont(format))
     }
 }
 
@@ -0,0 +1,613 @@
+//! Drawing images by sampling them in software.
+//!
+//! Raqote has no image sources yet, so images are sampled here and
+//! composited straight into the draw target's pixels. Both are
+//! premultiplied ARGB, stored as native `u32` values.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use kurbo::{Affine, Rect, Vec2};
+
+use piet::{affine_inverse, image_src_area, is_drawable_rect, mip_level, InterpolationMode};
+
+/// What a blit paints with.
+#[derive(Clone, Copy)]
+pub enum Paint {
+    /// The image's own colors.
+    Image,
+    /// A color, premultiplied RGBA from 0.0 to 255.0, with the image's
+    /// alpha as coverage.
+    Mask([f32; 4]),
+}
+
+/// A drawing of part of an image into a rectangle.
+pub struct Blit<'a> {
+    /// The image's pixels, premultiplied ARGB.
+    pub data: &'a [u32],
+    pub width: usize,
+    pub height: usize,
+    /// The part of the image to draw, in its pixels.
+    ///
+    /// Sampling never reaches outside the whole pixels it touches.
+    pub src_rect: Rect,
+    /// Where the part is drawn, in user space.
+    pub dst_rect: Rect,
+    /// The transform from user space to the target's pixels.
+    pub transform: Affine,
+    pub interp: InterpolationMode,
+    /// The opacity, from 0.0 to 1.0.
+    pub opacity: f32,
+    pub paint: Paint,
+    /// The image's mip levels, for high quality sampling.
+    pub mips: Option<&'a Mips>,
+}
+
+impl<'a> Blit<'a> {
+    /// Composite the blit over the pixels of a target.
+    ///
+    /// A target pixel is drawn when its center falls inside the destination
+    /// rectangle, so edges aren't anti-aliased. The `clip` is the coverage
+    /// of each target pixel, from 0 to 255, if anything is clipped.
+    pub fn draw(
+        &self,
+        target: &mut [u32],
+        target_width: usize,
+        target_height: usize,
+        clip: Option<&[u8]>,
+    ) {
+        let area = match image_src_area(self.src_rect, self.width, self.height) {
+            Some(area) => area,
+            None => return,
+        };
+        let (src, dst) = (self.src_rect, self.dst_rect);
+        if self.opacity <= 0.0 || self.opacity.is_nan() || !is_drawable_rect(dst) {
+            return;
+        }
+        let inverse = match affine_inverse(self.transform) {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let (x0, y0, x1, y1) = match self.target_bounds(target_width, target_height) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let scale_x = src.width() / dst.width();
+        let scale_y = src.height() / dst.height();
+        let area = SampleArea {
+            x0: area.x0 as i64,
+            y0: area.y0 as i64,
+            x1: area.x1 as i64 - 1,
+            y1: area.y1 as i64 - 1,
+        };
+        let texels = Texels {
+            data: self.data,
+            stride: self.width,
+            area,
+        };
+        // High quality sampling scales down with a mip level of the image.
+        let level = match (self.interp, self.mips) {
+            (InterpolationMode::HighQuality, Some(_)) => mip_level(self.transform, src, dst),
+            _ => 0,
+        };
+        let levels = match self.mips {
+            Some(mips) if level > 0 => mips.levels(self.data, self.width, self.height),
+            _ => Rc::new(Vec::new()),
+        };
+        let mip = match levels.len().min(level) {
+            0 => None,
+            level => Some(MipTexels::new(&levels[level - 1], level, area)),
+        };
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let point = inverse * Vec2::new(x as f64 + 0.5, y as f64 + 0.5);
+                let tx = (point.x - dst.x0) / dst.width();
+                let ty = (point.y - dst.y0) / dst.height();
+                if tx < 0.0 || tx >= 1.0 || ty < 0.0 || ty >= 1.0 {
+                    continue;
+                }
+                let coverage = match clip {
+                    Some(clip) => clip[y * target_width + x],
+                    None => 255,
+                };
+                if coverage == 0 {
+                    continue;
+                }
+                let src_point = Vec2::new(
+                    src.x0 + (point.x - dst.x0) * scale_x,
+                    src.y0 + (point.y - dst.y0) * scale_y,
+                );
+                let sample = match mip {
+                    Some(ref mip) => mip.sample(src_point),
+                    None => texels.sample(src_point, self.interp),
+                };
+                let color = scale(self.paint(sample), coverage as f32 / 255.0);
+                let pixel = &mut target[y * target_width + x];
+                *pixel = over(color, *pixel);
+            }
+        }
+    }
+
+    /// The target pixels the destination rectangle may cover, as half-open
+    /// ranges of columns and rows.
+    fn target_bounds(&self, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+        let dst = self.dst_rect;
+        let corners = [
+            Vec2::new(dst.x0, dst.y0),
+            Vec2::new(dst.x1, dst.y0),
+            Vec2::new(dst.x0, dst.y1),
+            Vec2::new(dst.x1, dst.y1),
+        ];
+        let first = self.transform * corners[0];
+        let (mut x0, mut y0, mut x1, mut y1) = (first.x, first.y, first.x, first.y);
+        for &corner in &corners[1..] {
+            let p = self.transform * corner;
+            x0 = x0.min(p.x);
+            y0 = y0.min(p.y);
+            x1 = x1.max(p.x);
+            y1 = y1.max(p.y);
+        }
+        let x0 = x0.floor().max(0.0);
+        let y0 = y0.floor().max(0.0);
+        let x1 = x1.ceil().min(width as f64);
+        let y1 = y1.ceil().min(height as f64);
+        // This also rejects NaN.
+        if !(x0 < x1 && y0 < y1) {
+            return None;
+        }
+        Some((x0 as usize, y0 as usize, x1 as usize, y1 as usize))
+    }
+
+    /// Apply the paint and opacity to a sampled color.
+    fn paint(&self, sample: [f32; 4]) -> [f32; 4] {
+        match self.paint {
+            Paint::Image => scale(sample, self.opacity),
+            Paint::Mask(color) => scale(color, sample[3] / 255.0 * self.opacity),
+        }
+    }
+}
+
+/// The pixels sampling is limited to, inclusive.
+#[derive(Clone, Copy)]
+struct SampleArea {
+    x0: i64,
+    y0: i64,
+    x1: i64,
+    y1: i64,
+}
+
+/// Premultiplied ARGB pixels, sampled within an area.
+struct Texels<'a> {
+    data: &'a [u32],
+    stride: usize,
+    area: SampleArea,
+}
+
+impl<'a> Texels<'a> {
+    /// A pixel, with its position clamped to the area.
+    fn get(&self, x: i64, y: i64) -> [f32; 4] {
+        let x = x.max(self.area.x0).min(self.area.x1) as usize;
+        let y = y.max(self.area.y0).min(self.area.y1) as usize;
+        unpack(self.data[y * self.stride + x])
+    }
+
+    /// Sample at a point in pixels, as premultiplied RGBA.
+    fn sample(&self, s: Vec2, interp: InterpolationMode) -> [f32; 4] {
+        match interp {
+            InterpolationMode::NearestNeighbor => self.get(s.x.floor() as i64, s.y.floor() as i64),
+            InterpolationMode::Bicubic => self.bicubic(s),
+            // Bilinear is the fallback for the other modes.
+            _ => self.bilinear(s),
+        }
+    }
+
+    fn bilinear(&self, s: Vec2) -> [f32; 4] {
+        // Pixel centers are at half-integer positions.
+        let (fx, fy) = (s.x - 0.5, s.y - 0.5);
+        let (ix, iy) = (fx.floor(), fy.floor());
+        let (tx, ty) = ((fx - ix) as f32, (fy - iy) as f32);
+        let (ix, iy) = (ix as i64, iy as i64);
+        let top = lerp(self.get(ix, iy), self.get(ix + 1, iy), tx);
+        let bottom = lerp(self.get(ix, iy + 1), self.get(ix + 1, iy + 1), tx);
+        lerp(top, bottom, ty)
+    }
+
+    /// Catmull-Rom interpolation of the 4x4 pixels around a point.
+    fn bicubic(&self, s: Vec2) -> [f32; 4] {
+        let (fx, fy) = (s.x - 0.5, s.y - 0.5);
+        let (ix, iy) = (fx.floor(), fy.floor());
+        let wx = cubic_weights((fx - ix) as f32);
+        let wy = cubic_weights((fy - iy) as f32);
+        let (ix, iy) = (ix as i64, iy as i64);
+        let mut result = [0.0; 4];
+        for (j, &wy) in wy.iter().enumerate() {
+            for (i, &wx) in wx.iter().enumerate() {
+                let texel = self.get(ix + i as i64 - 1, iy + j as i64 - 1);
+                for c in 0..4 {
+                    result[c] += texel[c] * wx * wy;
+                }
+            }
+        }
+        // The negative lobes can overshoot; keep the color premultiplied.
+        let alpha = result[3].max(0.0).min(255.0);
+        [
+            result[0].max(0.0).min(alpha),
+            result[1].max(0.0).min(alpha),
+            result[2].max(0.0).min(alpha),
+            alpha,
+        ]
+    }
+}
+
+/// The Catmull-Rom weights of four pixels, for a point `t` past the second.
+fn cubic_weights(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+/// An image halved in size one or more times.
+///
+/// Each halving averages 2x2 blocks of pixels, so that sampling it
+/// bilinearly doesn't skip over pixels when scaling down.
+pub struct MipLevel {
+    data: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+/// The mip levels of an image, built the first time they're needed.
+#[derive(Default)]
+pub struct Mips {
+    levels: RefCell<Option<Rc<Vec<MipLevel>>>>,
+}
+
+impl Mips {
+    /// The levels of an image, each half the size of the one before, down
+    /// to a single pixel. The first is half the size of the image.
+    fn levels(&self, data: &[u32], width: usize, height: usize) -> Rc<Vec<MipLevel>> {
+        let mut levels = self.levels.borrow_mut();
+        levels
+            .get_or_insert_with(|| Rc::new(build_mips(data, width, height)))
+            .clone()
+    }
+
+    /// Forget the levels, because the image's pixels have changed.
+    pub fn clear(&mut self) {
+        *self.levels.get_mut() = None;
+    }
+}
+
+fn build_mips(data: &[u32], width: usize, height: usize) -> Vec<MipLevel> {
+    let mut levels: Vec<MipLevel> = Vec::new();
+    let (mut data, mut width, mut height) = (data, width, height);
+    while width > 1 || height > 1 {
+        let half_width = (width + 1) / 2;
+        let half_height = (height + 1) / 2;
+        let mut half = Vec::with_capacity(half_width * half_height);
+        for y in 0..half_height {
+            let (y0, y1) = (2 * y, (2 * y + 1).min(height - 1));
+            for x in 0..half_width {
+                let (x0, x1) = (2 * x, (2 * x + 1).min(width - 1));
+                let mut sum = [0.0; 4];
+                for &(x, y) in &[(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+                    let texel = unpack(data[y * width + x]);
+                    for c in 0..4 {
+                        sum[c] += texel[c];
+                    }
+                }
+                half.push(pack(scale(sum, 0.25)));
+            }
+        }
+        levels.push(MipLevel {
+            data: half,
+            width: half_width,
+            height: half_height,
+        });
+        let level = levels.last().unwrap();
+        data = &level.data;
+        width = level.width;
+        height = level.height;
+    }
+    levels
+}
+
+/// A mip level, sampled within the part covering a sample area.
+struct MipTexels<'a> {
+    texels: Texels<'a>,
+    /// How many image pixels each of its pixels covers in each direction.
+    factor: f64,
+}
+
+impl<'a> MipTexels<'a> {
+    /// Level `level` of the mips, the first level being 1.
+    fn new(mip: &'a MipLevel, level: usize, area: SampleArea) -> MipTexels<'a> {
+        // Pixels at the edge of the area may average in a little of their
+        // neighbors, but no more.
+        let area = SampleArea {
+            x0: area.x0 >> level,
+            y0: area.y0 >> level,
+            x1: area.x1 >> level,
+            y1: area.y1 >> level,
+        };
+        MipTexels {
+            texels: Texels {
+                data: &mip.data,
+                stride: mip.width,
+                area,
+            },
+            factor: (1u64 << level) as f64,
+        }
+    }
+
+    /// Sample at a point in the image's pixels.
+    fn sample(&self, s: Vec2) -> [f32; 4] {
+        self.texels.bilinear(s * (1.0 / self.factor))
+    }
+}
+
+/// Split a premultiplied ARGB pixel into RGBA channels.
+fn unpack(argb: u32) -> [f32; 4] {
+    [
+        ((argb >> 16) & 0xff) as f32,
+        ((argb >> 8) & 0xff) as f32,
+        (argb & 0xff) as f32,
+        (argb >> 24) as f32,
+    ]
+}
+
+/// Join RGBA channels into a premultiplied ARGB pixel, rounding them.
+fn pack(c: [f32; 4]) -> u32 {
+    let channel = |x: f32| (x + 0.5).max(0.0).min(255.0) as u32;
+    (channel(c[3]) << 24) | (channel(c[0]) << 16) | (channel(c[1]) << 8) | channel(c[2])
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for i in 0..4 {
+        result[i] = a[i] + (b[i] - a[i]) * t;
+    }
+    result
+}
+
+fn scale(c: [f32; 4], k: f32) -> [f32; 4] {
+    [c[0] * k, c[1] * k, c[2] * k, c[3] * k]
+}
+
+/// Composite a premultiplied color over a target pixel.
+fn over(src: [f32; 4], dst: u32) -> u32 {
+    if src[3] <= 0.0 {
+        return dst;
+    }
+    let dst = unpack(dst);
+    let k = 1.0 - src[3] / 255.0;
+    pack([
+        src[0] + dst[0] * k,
+        src[1] + dst[1] * k,
+        src[2] + dst[2] * k,
+        src[3] + dst[3] * k,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0xff_ff_00_00;
+    const BLACK: u32 = 0xff_00_00_00;
+    const WHITE: u32 = 0xff_ff_ff_ff;
+
+    fn blit(data: &[u32], width: usize, src_rect: Rect, dst_rect: Rect) -> Blit {
+        Blit {
+            data,
+            width,
+            height: data.len() / width,
+            src_rect,
+            dst_rect,
+            transform: Affine::default(),
+            interp: InterpolationMode::NearestNeighbor,
+            opacity: 1.0,
+            paint: Paint::Image,
+            mips: None,
+        }
+    }
+
+    #[test]
+    fn nearest_scales_up() {
+        let data = [RED, BLACK];
+        let mut target = [WHITE; 8];
+        blit(
+            &data,
+            2,
+            Rect::new(0.0, 0.0, 2.0, 1.0),
+            Rect::new(0.0, 0.0, 4.0, 2.0),
+        )
+        .draw(&mut target, 4, 2, None);
+        assert_eq!(target, [RED, RED, BLACK, BLACK, RED, RED, BLACK, BLACK]);
+    }
+
+    #[test]
+    fn only_pixel_centers_inside_are_drawn() {
+        let data = [RED];
+        let mut target = [WHITE; 4];
+        blit(
+            &data,
+            1,
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+            Rect::new(0.6, 0.0, 2.6, 1.0),
+        )
+        .draw(&mut target, 4, 1, None);
+        assert_eq!(target, [WHITE, RED, RED, WHITE]);
+    }
+
+    #[test]
+    fn transform_moves_the_image() {
+        let data = [RED];
+        let mut target = [WHITE; 4];
+        let mut blit = blit(
+            &data,
+            1,
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+        );
+        blit.transform = Affine::translate((2.0, 0.0));
+        blit.draw(&mut target, 4, 1, None);
+        assert_eq!(target, [WHITE, WHITE, RED, WHITE]);
+    }
+
+    #[test]
+    fn bilinear_stays_in_the_source_area() {
+        let data = [RED, BLACK];
+        let mut target = [WHITE; 8];
+        let mut blit = blit(
+            &data,
+            2,
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+            Rect::new(0.0, 0.0, 8.0, 1.0),
+        );
+        blit.interp = InterpolationMode::Bilinear;
+        blit.draw(&mut target, 8, 1, None);
+        assert_eq!(target, [RED; 8]);
+    }
+
+    #[test]
+    fn bilinear_blends_neighbors() {
+        let data = [BLACK, WHITE];
+        let mut target = [0; 4];
+        let mut blit = blit(
+            &data,
+            2,
+            Rect::new(0.0, 0.0, 2.0, 1.0),
+            Rect::new(0.0, 0.0, 4.0, 1.0),
+        );
+        blit.interp = InterpolationMode::Bilinear;
+        blit.draw(&mut target, 4, 1, None);
+        assert_eq!(target, [BLACK, 0xff_40_40_40, 0xff_bf_bf_bf, WHITE]);
+    }
+
+    #[test]
+    fn bicubic_keeps_pixel_centers_and_doesnt_overshoot() {
+        let data = [BLACK, BLACK, WHITE, WHITE];
+        let mut target = [0; 8];
+        let mut blit = blit(
+            &data,
+            4,
+            Rect::new(0.0, 0.0, 4.0, 1.0),
+            Rect::new(0.0, 0.0, 8.0, 1.0),
+        );
+        blit.interp = InterpolationMode::Bicubic;
+        blit.draw(&mut target, 8, 1, None);
+        // Pixel centers fall between the source centers, except at the
+        // edges, and Catmull-Rom stays within the neighbors there.
+        assert_eq!(target[0], BLACK);
+        assert_eq!(target[7], WHITE);
+        for pair in target.windows(2) {
+            assert!(pair[0] & 0xff <= pair[1] & 0xff);
+            assert_eq!(pair[0] >> 24, 0xff);
+        }
+    }
+
+    #[test]
+    fn high_quality_averages_when_scaling_down() {
+        let data = [BLACK, WHITE, BLACK, WHITE, BLACK, WHITE, BLACK, WHITE];
+        let mut target = [0; 2];
+        let mut blit = blit(
+            &data,
+            8,
+            Rect::new(0.0, 0.0, 8.0, 1.0),
+            Rect::new(0.0, 0.0, 2.0, 1.0),
+        );
+        blit.interp = InterpolationMode::Bilinear;
+        blit.draw(&mut target, 2, 1, None);
+        // Bilinear sampling lands between two pixels and misses the rest.
+        assert_eq!(target, [0xff_80_80_80; 2]);
+        let data = [BLACK, BLACK, BLACK, WHITE, BLACK, BLACK, BLACK, BLACK];
+        blit.data = &data;
+        blit.draw(&mut target, 2, 1, None);
+        assert_eq!(target, [BLACK; 2]);
+        let mips = Mips::default();
+        blit.interp = InterpolationMode::HighQuality;
+        blit.mips = Some(&mips);
+        blit.draw(&mut target, 2, 1, None);
+        assert_eq!(target[0], 0xff_40_40_40);
+        assert!(target[1] & 0xff < 0x40);
+    }
+
+    #[test]
+    fn high_quality_stays_in_the_source_area() {
+        let data = [RED, RED, BLACK, BLACK];
+        let mut target = [WHITE; 1];
+        let mut blit = blit(
+            &data,
+            4,
+            Rect::new(0.0, 0.0, 2.0, 1.0),
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+        );
+        let mips = Mips::default();
+        blit.interp = InterpolationMode::HighQuality;
+        blit.mips = Some(&mips);
+        blit.draw(&mut target, 1, 1, None);
+        assert_eq!(target, [RED]);
+    }
+
+    #[test]
+    fn opacity_blends_over_the_target() {
+        let data = [RED];
+        let mut target = [WHITE];
+        let mut blit = blit(
+            &data,
+            1,
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+            Rect::new(0.0, 0.0, 1.0, 1.0),
+        );
+        blit.opacity = 0.5;
+        blit.draw(&mut target, 1, 1, None);
+        assert_eq!(target, [0xff_ff_80_80]);
+    }
+
+    #[test]
+    fn mask_paints_the_color() {
+        let data = [0xff_00_00_00, 0x80_00_00_00, 0];
+        let mut target = [WHITE; 3];
+        let mut blit = blit(
+            &data,
+            3,
+            Rect::new(0.0, 0.0, 3.0, 1.0),
+            Rect::new(0.0, 0.0, 3.0, 1.0),
+        );
+        blit.paint = Paint::Mask([255.0, 0.0, 0.0, 255.0]);
+        blit.draw(&mut target, 3, 1, None);
+        assert_eq!(target, [RED, 0xff_ff_7f_7f, WHITE]);
+    }
+
+    #[test]
+    fn clip_coverage_masks_the_image() {
+        let data = [RED];
+        let mut target = [WHITE; 3];
+        let rect = Rect::new(0.0, 0.0, 3.0, 1.0);
+        blit(&data, 1, Rect::new(0.0, 0.0, 1.0, 1.0), rect).draw(
+            &mut target,
+            3,
+            1,
+            Some(&[255, 128, 0]),
+        );
+        assert_eq!(target, [RED, 0xff_ff_7f_7f, WHITE]);
+    }
+
+    #[test]
+    fn degenerate_blits_draw_nothing() {
+        let data = [RED];
+        let mut target = [WHITE; 4];
+        let src = Rect::new(0.0, 0.0, 1.0, 1.0);
+        blit(&data, 1, src, Rect::new(1.0, 0.0, 1.0, 1.0)).draw(&mut target, 4, 1, None);
+        blit(&data, 1, Rect::new(2.0, 0.0, 3.0, 1.0), src).draw(&mut target, 4, 1, None);
+        let mut singular = blit(&data, 1, src, src);
+        singular.transform = Affine::scale(0.0);
+        singular.draw(&mut target, 4, 1, None);
+        assert_eq!(target, [WHITE; 4]);
+    }
+}
@@ -1,49 +1,186 @@
 //! The Raqote backend for the Piet 2D graphics abstraction.
 
-use raqote::{DrawTarget, PathBuilder, SolidSource, Source, Winding};
+mod blit;
+
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use raqote::{DrawTarget, Path, PathBuilder, Point, SolidSource, Source, Winding};
 
 use kurbo::{Affine, PathEl, QuadBez, Rect, Shape, Vec2};
 
-use euclid::{Angle, Point2D, Transform2D};
+use euclid::Transform2D;
+
+use crate::blit::{Blit, Mips, Paint};
 
 use piet::{
-    new_error, Error, ErrorKind, FillRule, Font, FontBuilder, Gradient, GradientStop, ImageFormat,
-    InterpolationMode, LineCap, LineJoin, RenderContext, RoundInto, StrokeStyle, Text, TextLayout,
-    TextLayoutBuilder,
+    argb32_to_rgba_premul, capture_pixel_area, check_image_buf, convert_from_rgba_premul,
+    convert_to_rgba_premul, dither_to_rgba_separate, image_dirty_area, new_error, packed_image_buf,
+    DrawImageOptions, Error, ErrorKind, FillRule, Font, FontBuilder, Gradient, GradientStop, Image,
+    ImageFormat, InterpolationMode, LineCap, LineJoin, Offscreen, RenderContext, RoundInto,
+    StrokeStyle, Text, TextLayout, TextLayoutBuilder,
 };
 
 #[derive(Default)]
 struct CtxState {
     transform: Affine,
+    /// The coverage of the clip, one byte per target pixel, for drawing
+    /// images. `None` when nothing is clipped.
+    clip: Option<Rc<Vec<u8>>>,
+    /// The number of clips pushed on the draw target in this state.
+    n_clips: usize,
 }
 
 pub struct RaqoteRenderContext<'a> {
-    draw_target: &'a mut DrawTarget,
+    draw_target: DrawTargetRef<'a>,
+    /// The context state stack. There is always at least one, until finishing.
     ctx_stack: Vec<CtxState>,
 
     // TODO: Do actual text
     text: RaqoteText,
+
+    /// The first error from an operation that can't return one, reported
+    /// by `status` and `finish`.
+    err: Result<(), Error>,
 }
 
 impl<'a> RaqoteRenderContext<'a> {
     pub fn new(draw_target: &'a mut DrawTarget) -> RaqoteRenderContext<'a> {
         RaqoteRenderContext {
-            draw_target,
+            draw_target: DrawTargetRef::Borrowed(draw_target),
             text: RaqoteText,
             ctx_stack: vec![CtxState::default()],
+            err: Ok(()),
         }
     }
 
     fn current_transform(&self) -> Affine {
-        // This is an unwrap because we protect the invariant.
+        // This is an unwrap because we protect the invariant, and callers
+        // check that the context hasn't been finished.
         self.ctx_stack.last().unwrap().transform
     }
 
     fn pop_state(&mut self) {
-        self.ctx_stack.pop();
+        if let Some(state) = self.ctx_stack.pop() {
+            for _ in 0..state.n_clips {
+                self.draw_target.pop_clip();
+            }
+        }
+    }
+
+    /// Record an error from the named operation, unless one is already pending.
+    fn record_err(&mut self, op: &'static str, e: Error) {
+        if self.err.is_ok() {
+            self.err = Err(e.with_op(op));
+        }
+    }
+
+    /// Draw a blit for the named operation, on top of the current transform.
+    fn draw_blit(&mut self, op: &'static str, mut blit: Blit) {
+        let state = match self.ctx_stack.last() {
+            Some(state) => state,
+            None => {
+                // The context has already been finished.
+                self.record_err(op, new_error(ErrorKind::InvalidInput));
+                return;
+            }
+        };
+        blit.transform = state.transform * blit.transform;
+        let clip = state.clip.as_ref().map(|clip| &clip[..]);
+        let width = self.draw_target.width() as usize;
+        let height = self.draw_target.height() as usize;
+        blit.draw(self.draw_target.get_data_mut(), width, height, clip);
+    }
+
+    /// The coverage of a clip path, intersected with the current clip.
+    ///
+    /// Images are drawn into the target's pixels directly, so they can't
+    /// use Raqote's clip, and are masked with this instead.
+    fn clip_coverage(&self, path: &Path, transform: Affine) -> Vec<u8> {
+        let mut coverage_target =
+            DrawTarget::new(self.draw_target.width(), self.draw_target.height());
+        coverage_target.set_transform(&affine_to_transform(transform));
+        let opaque = Source::Solid(SolidSource {
+            r: 0xff,
+            g: 0xff,
+            b: 0xff,
+            a: 0xff,
+        });
+        coverage_target.fill(path, &opaque, Winding::NonZero);
+        let mut coverage: Vec<u8> = coverage_target
+            .get_data()
+            .iter()
+            .map(|&argb| (argb >> 24) as u8)
+            .collect();
+        if let Some(clip) = self.ctx_stack.last().and_then(|state| state.clip.as_ref()) {
+            for (c, &outer) in coverage.iter_mut().zip(clip.iter()) {
+                *c = ((*c as u32 * outer as u32 + 127) / 255) as u8;
+            }
+        }
+        coverage
+    }
+}
+
+/// The draw target, which is owned by offscreen targets.
+enum DrawTargetRef<'a> {
+    Borrowed(&'a mut DrawTarget),
+    Owned(DrawTarget),
+}
+
+impl<'a> Deref for DrawTargetRef<'a> {
+    type Target = DrawTarget;
+
+    fn deref(&self) -> &DrawTarget {
+        match *self {
+            DrawTargetRef::Borrowed(ref draw_target) => draw_target,
+            DrawTargetRef::Owned(ref draw_target) => draw_target,
+        }
+    }
+}
+
+impl<'a> DerefMut for DrawTargetRef<'a> {
+    fn deref_mut(&mut self) -> &mut DrawTarget {
+        match *self {
+            DrawTargetRef::Borrowed(ref mut draw_target) => draw_target,
+            DrawTargetRef::Owned(ref mut draw_target) => draw_target,
+        }
+    }
+}
+
+/// An image, with premultiplied ARGB pixels like a `DrawTarget`.
+pub struct RaqoteImage {
+    width: usize,
+    height: usize,
+    format: ImageFormat,
+    data: Vec<u32>,
+    mips: Mips,
+}
+
+impl RaqoteImage {
+    /// A blit drawing the whole image into a rectangle.
+    fn blit(&self, rect: Rect, interp: InterpolationMode) -> Blit {
+        Blit {
+            data: &self.data,
+            width: self.width,
+            height: self.height,
+            src_rect: Rect::new(0.0, 0.0, self.width as f64, self.height as f64),
+            dst_rect: rect,
+            transform: Affine::default(),
+            interp,
+            opacity: 1.0,
+            paint: Paint::Image,
+            mips: Some(&self.mips),
+        }
     }
 }
 
+/// A target for drawing into an image, with its own `DrawTarget`.
+pub struct RaqoteOffscreen {
+    width: usize,
+    height: usize,
+    rc: RaqoteRenderContext<'static>,
+}
+
 pub struct RaqoteText;
 
 pub struct RaqoteFont;
@@ -79,6 +216,35 @@ fn convert_line_cap(line_cap: LineCap) -> raqote::LineCap {
     }
 }
 
+fn convert_path(shape: impl Shape) -> Path {
+    let mut builder = PathBuilder::new();
+    for el in shape.to_bez_path(1e-3) {
+        match el {
+            PathEl::Moveto(p) => {
+                builder.move_to(p.x as f32, p.y as f32);
+            }
+            PathEl::Lineto(p) => {
+                builder.line_to(p.x as f32, p.y as f32);
+            }
+            PathEl::Quadto(p1, p2) => {
+                builder.quad_to(p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32);
+            }
+            PathEl::Curveto(p1, p2, p3) => {
+                builder.cubic_to(
+                    p1.x as f32,
+                    p1.y as f32,
+                    p2.x as f32,
+                    p2.y as f32,
+                    p3.x as f32,
+                    p3.y as f32,
+                );
+            }
+            PathEl::Closepath => builder.close(),
+        }
+    }
+    builder.finish()
+}
+
 fn convert_dash(dash: &(Vec<f64>, f64)) -> (Vec<f32>, f32) {
     // TODO: find cheaper way to do this?
     (dash.0.iter().map(|d| *d as f32).collect(), dash.1 as f32)
@@ -101,34 +267,42 @@ fn rgba_to_arbg(rgba: u32) -> u32 {
     (rgba << 24) | (rgba >> 8)
 }
 
-// Generates a 2D transform for rendering linear gradients in Raqot
-// If Raqot is given an identity transform, it will render linear gradients from (0, 0) to (256, 0)
-// This function generates a transforms such that the linear gradient will be drawn
-// between the provided start and end points.
-fn linear_points_to_transform(start: Vec2, end: Vec2) -> Transform2D<f32> {
-    let gradient_vector = end - start;
-    // Move to start point
-    let translate = Transform2D::create_translation(start.x as f32, start.y as f32);
-    // Get length of gradient vector
-    let length = gradient_vector.hypot() as f32;
-    // Linear grandients in raqot go from (0, 0) to (256, 0), this may change in the future
-    // Scaling is multiplication not division (2, not 0.5)
-    let scale = Transform2D::create_scale(length / 256.0, length / 256.0);
-    // Get correct angle
-    let rotation = Transform2D::create_rotation(-Angle::radians(gradient_vector.atan2() as f32));
-
-    // TODO: Move `inverse()` to Raqote
-    translate.pre_mul(&rotation).pre_mul(&scale).inverse().unwrap()
+// Convert a premultiplied RGBA pixel to a premultiplied ARGB u32
+fn rgba_to_argb_premul(p: &[u8]) -> u32 {
+    (p[3] as u32) << 24 | (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32
 }
 
-// Generates a 2D transform for rendering radial gradients in Raqot
-fn radial_points_to_transform(center: Vec2, _origin_offset: Vec2, radius: f32) -> Transform2D<f32> {
-    // Max distance is 32768
-    let scale = Transform2D::create_scale(radius / 128.0, radius / 128.0);
-    let translate = Transform2D::create_translation(center.x as f32, center.y as f32);
+fn vec2_to_point(v: Vec2) -> Point {
+    Point::new(v.x as f32, v.y as f32)
+}
+
+fn convert_gradient_stops(stops: &[GradientStop]) -> raqote::Gradient {
+    raqote::Gradient {
+        stops: stops
+            .iter()
+            .map(|stop| raqote::GradientStop {
+                position: stop.pos,
+                color: rgba_to_arbg(stop.rgba),
+            })
+            .collect(),
+    }
+}
 
-    // TODO: Move `inverse()` to Raqote
-    translate.pre_mul(&scale).inverse().unwrap()
+/// The largest width or height of an image or offscreen target.
+///
+/// This is the same as Cairo's limit, and keeps the number of pixels well
+/// within what can be allocated.
+const MAX_IMAGE_SIZE: usize = 32767;
+
+/// Check that a draw target of the given size can be created.
+fn check_image_size(width: usize, height: usize) -> Result<(), Error> {
+    if width == 0 || height == 0 || width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+        return Err(new_error(ErrorKind::InvalidImageSize).with_context(format!(
+            "image size {}x{} is empty or exceeds the limit of {}",
+            width, height, MAX_IMAGE_SIZE
+        )));
+    }
+    Ok(())
 }
 
 impl<'a> RenderContext for RaqoteRenderContext<'a> {
@@ -140,10 +314,11 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
     type Text = RaqoteText;
     type TextLayout = RaqoteTextLayout;
 
-    type Image = ();
+    type Image = RaqoteImage;
+    type Offscreen = RaqoteOffscreen;
 
     fn status(&mut self) -> Result<(), Error> {
-        Ok(())
+        std::mem::replace(&mut self.err, Ok(()))
     }
 
     fn solid_brush(&mut self, rgba: u32) -> Result<Self::Brush, Error> {
@@ -153,39 +328,32 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
 
     fn gradient(&mut self, gradient: Gradient) -> Result<Self::Brush, Error> {
         match gradient {
-            Gradient::Linear(gradient) => Ok(Source::LinearGradient(
-                raqote::Gradient {
-                    stops: gradient
-                        .stops
-                        .iter()
-                        .map(|stop| raqote::GradientStop {
-                            position: stop.pos,
-                            color: rgba_to_arbg(stop.rgba),
-                        })
-                        .collect(),
-                },
-                linear_points_to_transform(gradient.start, gradient.end),
-            )),
-            Gradient::Radial(gradient) => Ok(Source::RadialGradient(
-                raqote::Gradient {
-                    stops: gradient
-                        .stops
-                        .iter()
-                        .map(|stop| raqote::GradientStop {
-                            position: stop.pos,
-                            color: rgba_to_arbg(stop.rgba),
-                        })
-                        .collect(),
-                },
-                radial_points_to_transform(gradient.center, gradient.origin_offset, gradient.radius as f32),
+            Gradient::Linear(linear) => Ok(Source::new_linear_gradient(
+                convert_gradient_stops(&linear.stops),
+                vec2_to_point(linear.start),
+                vec2_to_point(linear.end),
             )),
+            Gradient::Radial(radial) => {
+                // Same as the other backends: the gradient starts at a zero-radius
+                // circle on the origin and ends on the circle of the given radius.
+                let origin = radial.center + radial.origin_offset;
+                Ok(Source::new_two_circle_radial_gradient(
+                    convert_gradient_stops(&radial.stops),
+                    vec2_to_point(origin),
+                    0.0,
+                    vec2_to_point(radial.center),
+                    radial.radius as f32,
+                ))
+            }
         }
     }
 
-    fn clear(&mut self, _rgb: u32) {
-        // TODO: Fork Raqote to either (or both)
-        // 1. Clear command
-        // 2. Expose width and height
+    fn clear(&mut self, rgb: u32) {
+        // Like Direct2D, clearing ignores the transform and fills the whole target.
+        let argb = 0xff_00_00_00 | (rgb & 0xff_ff_ff);
+        for pixel in self.draw_target.get_data_mut() {
+            *pixel = argb;
+        }
     }
 
     fn stroke(
@@ -195,33 +363,7 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
         width: impl RoundInto<Self::Coord>,
         style: Option<&StrokeStyle>,
     ) {
-        // TODO: Expose Path in Raqote so this can be moved to a function
-        let mut builder = PathBuilder::new();
-        for el in shape.to_bez_path(1e-3) {
-            match el {
-                PathEl::Moveto(p) => {
-                    builder.move_to(p.x as f32, p.y as f32);
-                }
-                PathEl::Lineto(p) => {
-                    builder.line_to(p.x as f32, p.y as f32);
-                }
-                PathEl::Quadto(p1, p2) => {
-                    builder.quad_to(p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32);
-                }
-                PathEl::Curveto(p1, p2, p3) => {
-                    builder.cubic_to(
-                        p1.x as f32,
-                        p1.y as f32,
-                        p2.x as f32,
-                        p2.y as f32,
-                        p3.x as f32,
-                        p3.y as f32,
-                    );
-                }
-                PathEl::Closepath => builder.close(),
-            }
-        }
-        let path = builder.finish();
+        let path = convert_path(shape);
 
         // TODO: Factor this out
         let cap = style
@@ -259,33 +401,7 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
     }
 
     fn fill(&mut self, shape: impl Shape, brush: &Self::Brush, fill_rule: FillRule) {
-        // TODO: Expose Path in Raqote so this can be moved to a function
-        let mut builder = PathBuilder::new();
-        for el in shape.to_bez_path(1e-3) {
-            match el {
-                PathEl::Moveto(p) => {
-                    builder.move_to(p.x as f32, p.y as f32);
-                }
-                PathEl::Lineto(p) => {
-                    builder.line_to(p.x as f32, p.y as f32);
-                }
-                PathEl::Quadto(p1, p2) => {
-                    builder.quad_to(p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32);
-                }
-                PathEl::Curveto(p1, p2, p3) => {
-                    builder.cubic_to(
-                        p1.x as f32,
-                        p1.y as f32,
-                        p2.x as f32,
-                        p2.y as f32,
-                        p3.x as f32,
-                        p3.y as f32,
-                    );
-                }
-                PathEl::Closepath => builder.close(),
-            }
-        }
-        let path = builder.finish();
+        let path = convert_path(shape);
 
         let winding_mode = match fill_rule {
             FillRule::EvenOdd => Winding::EvenOdd,
@@ -295,8 +411,22 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
         self.draw_target.fill(&path, brush, winding_mode);
     }
 
-    fn clip(&mut self, shape: impl Shape, fill_rule: FillRule) {
-        // TODO
+    fn clip(&mut self, shape: impl Shape, _fill_rule: FillRule) {
+        let transform = match self.ctx_stack.last() {
+            Some(state) => state.transform,
+            None => {
+                // The context has already been finished.
+                self.record_err("clip", new_error(ErrorKind::InvalidInput));
+                return;
+            }
+        };
+        // TODO: even-odd clips, once Raqote's clip paths have a winding rule.
+        let path = convert_path(shape);
+        let coverage = self.clip_coverage(&path, transform);
+        self.draw_target.push_clip(&path);
+        let state = self.ctx_stack.last_mut().unwrap();
+        state.clip = Some(Rc::new(coverage));
+        state.n_clips += 1;
     }
 
     fn text(&mut self) -> &mut Self::Text {
@@ -314,8 +444,14 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
     }
 
     fn save(&mut self) -> Result<(), Error> {
-        let new_state = CtxState {
-            transform: self.current_transform(),
+        let new_state = match self.ctx_stack.last() {
+            Some(state) => CtxState {
+                transform: state.transform,
+                clip: state.clip.clone(),
+                n_clips: 0,
+            },
+            // The context has already been finished.
+            None => return Err(new_error(ErrorKind::InvalidInput)),
         };
         self.ctx_stack.push(new_state);
         Ok(())
@@ -327,8 +463,8 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
         }
         self.pop_state();
         // Move this code into impl to avoid duplication with transform?
-        self.draw_target
-            .set_transform(&affine_to_transform(self.current_transform()));
+        let transform = affine_to_transform(self.current_transform());
+        self.draw_target.set_transform(&transform);
         Ok(())
     }
 
@@ -337,22 +473,79 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
             return Err(new_error(ErrorKind::StackUnbalance));
         }
         self.pop_state();
-        Ok(())
+        self.status()
     }
 
     fn transform(&mut self, transform: Affine) {
-        self.ctx_stack.last_mut().unwrap().transform *= transform;
-        self.draw_target
-            .set_transform(&affine_to_transform(self.current_transform()));
+        match self.ctx_stack.last_mut() {
+            Some(state) => state.transform *= transform,
+            None => {
+                // The context has already been finished.
+                self.record_err("transform", new_error(ErrorKind::InvalidInput));
+                return;
+            }
+        }
+        let transform = affine_to_transform(self.current_transform());
+        self.draw_target.set_transform(&transform);
     }
 
-    fn make_image(
+    fn make_image_with_stride(
         &mut self,
         width: usize,
         height: usize,
+        stride: usize,
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
+        if format.bits_per_channel() > 8 {
+            // Images are stored with 8 bits per channel, so dither down.
+            let buf = dither_to_rgba_separate(width, height, stride, buf, format)?;
+            return self.make_image(width, height, &buf, ImageFormat::RgbaSeparate);
+        }
+        check_image_buf(width, height, stride, buf, format)?;
+        let buf = packed_image_buf(width, height, stride, buf, format);
+        let mut rgba = vec![0; width * height * 4];
+        convert_to_rgba_premul(&buf, &mut rgba, format)?;
+        let data = rgba.chunks(4).map(rgba_to_argb_premul).collect();
+        Ok(RaqoteImage {
+            width,
+            height,
+            format,
+            data,
+            mips: Mips::default(),
+        })
+    }
+
+    fn update_image(
+        &mut self,
+        image: &mut Self::Image,
+        dirty_rect: impl Into<Rect>,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let area = image_dirty_area(dirty_rect.into(), image.width, image.height)?;
+        let (width, height) = (area.width() as usize, area.height() as usize);
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let src_stride = width * format.bytes_per_pixel();
+        if format.bits_per_channel() > 8 {
+            let buf = dither_to_rgba_separate(width, height, src_stride, buf, format)?;
+            return self.update_image(image, area, &buf, ImageFormat::RgbaSeparate);
+        }
+        check_image_buf(width, height, src_stride, buf, format)?;
+        let mut rgba = vec![0; width * height * 4];
+        convert_to_rgba_premul(&buf[..src_stride * height], &mut rgba, format)?;
+        for (y, row) in rgba.chunks(width * 4).enumerate() {
+            let dst_off = (area.y0 as usize + y) * image.width + area.x0 as usize;
+            for (dst, p) in image.data[dst_off..dst_off + width]
+                .iter_mut()
+                .zip(row.chunks(4))
+            {
+                *dst = rgba_to_argb_premul(p);
+            }
+        }
+        image.mips.clear();
         Ok(())
     }
 
@@ -362,7 +555,159 @@ impl<'a> RenderContext for RaqoteRenderContext<'a> {
         rect: impl Into<Rect>,
         interp: InterpolationMode,
     ) {
+        self.draw_blit("draw_image", image.blit(rect.into(), interp));
+    }
+
+    fn draw_image_with_options(
+        &mut self,
+        image: &Self::Image,
+        rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        let mut blit = image.blit(rect.into(), interp);
+        blit.transform = options.transform();
+        blit.opacity = options.opacity() as f32;
+        let clip = match options.clip() {
+            Some(clip) => clip,
+            None => {
+                self.draw_blit("draw_image_with_options", blit);
+                return;
+            }
+        };
+        let result = self.with_save(|rc| {
+            rc.clip(clip.clone(), FillRule::NonZero);
+            rc.draw_blit("draw_image_with_options", blit);
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.record_err("draw_image_with_options", e);
+        }
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    ) {
+        let mut blit = image.blit(dst_rect.into(), interp);
+        blit.src_rect = src_rect.into();
+        self.draw_blit("draw_image_area", blit);
+    }
+
+    fn draw_mask(&mut self, mask: &Self::Image, rect: impl Into<Rect>, brush: &Self::Brush) {
+        let color = match *brush {
+            Source::Solid(SolidSource { r, g, b, a }) => {
+                // Solid brushes hold separate alpha, and the blitter wants
+                // premultiplied channels.
+                let alpha = a as f32 / 255.0;
+                [
+                    r as f32 * alpha,
+                    g as f32 * alpha,
+                    b as f32 * alpha,
+                    a as f32,
+                ]
+            }
+            _ => {
+                // TODO: sample gradients under the mask.
+                self.record_err("draw_mask", new_error(ErrorKind::NotSupported));
+                return;
+            }
+        };
+        let mut blit = mask.blit(rect.into(), InterpolationMode::Bilinear);
+        blit.paint = Paint::Mask(color);
+        self.draw_blit("draw_mask", blit);
+    }
+
+    fn create_offscreen(&mut self, width: usize, height: usize) -> Result<Self::Offscreen, Error> {
+        check_image_size(width, height)?;
+        let draw_target = DrawTarget::new(width as i32, height as i32);
+        Ok(RaqoteOffscreen {
+            width,
+            height,
+            rc: RaqoteRenderContext {
+                draw_target: DrawTargetRef::Owned(draw_target),
+                text: RaqoteText,
+                ctx_stack: vec![CtxState::default()],
+                err: Ok(()),
+            },
+        })
+    }
+
+    fn capture_image_area(&mut self, rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let area = capture_pixel_area(rect.into())?;
+        let (width, height) = (area.width() as usize, area.height() as usize);
+        check_image_size(width, height)?;
+        let target_width = self.draw_target.width() as i64;
+        let target_height = self.draw_target.height() as i64;
+        let src = self.draw_target.get_data();
+        let mut data = vec![0; width * height];
+        for y in 0..height {
+            let src_y = area.y0 as i64 + y as i64;
+            if src_y < 0 || src_y >= target_height {
+                continue;
+            }
+            for x in 0..width {
+                let src_x = area.x0 as i64 + x as i64;
+                if src_x >= 0 && src_x < target_width {
+                    data[y * width + x] = src[(src_y * target_width + src_x) as usize];
+                }
+            }
+        }
+        Ok(RaqoteImage {
+            width,
+            height,
+            format: ImageFormat::RgbaPremul,
+            data,
+            mips: Mips::default(),
+        })
+    }
+}
+
+impl Image for RaqoteImage {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    fn to_raw_pixels(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let mut rgba = vec![0; self.data.len() * 4];
+        argb32_to_rgba_premul(&self.data, &mut rgba)?;
+        if format == ImageFormat::RgbaPremul {
+            return Ok(rgba);
+        }
+        let mut buf = vec![0; self.width * self.height * format.bytes_per_pixel()];
+        // As documented on Image, formats without alpha go over black.
+        convert_from_rgba_premul(&rgba, &mut buf, format, 0x00_00_00)?;
+        Ok(buf)
+    }
+}
+
+impl Offscreen for RaqoteOffscreen {
+    type Image = RaqoteImage;
+    type Context = RaqoteRenderContext<'static>;
+
+    fn render_context(&mut self) -> &mut Self::Context {
+        &mut self.rc
+    }
 
+    fn into_image(mut self) -> Result<Self::Image, Error> {
+        self.rc.finish()?;
+        Ok(RaqoteImage {
+            width: self.width,
+            height: self.height,
+            format: ImageFormat::RgbaPremul,
+            data: self.rc.draw_target.get_data().to_vec(),
+            mips: Mips::default(),
+        })
     }
 }
 
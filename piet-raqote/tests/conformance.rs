@@ -0,0 +1,32 @@
+//! Run the piet conformance probes on Raqote.
+
+use raqote::DrawTarget;
+
+use piet::{Error, RenderContext};
+use piet_conformance::{assert_conformance, Backend, Pixels, Probe};
+use piet_raqote::RaqoteRenderContext;
+
+struct Raqote;
+
+impl Backend for Raqote {
+    fn render(&mut self, width: usize, height: usize, probe: Probe) -> Result<Pixels, Error> {
+        let mut draw_target = DrawTarget::new(width as i32, height as i32);
+        {
+            let mut rc = RaqoteRenderContext::new(&mut draw_target);
+            probe.draw(&mut rc)?;
+            rc.finish()?;
+        }
+        let mut rgba = vec![0; width * height * 4];
+        piet::argb32_to_rgba_premul(draw_target.get_data(), &mut rgba)?;
+        Ok(Pixels {
+            width,
+            height,
+            data: rgba,
+        })
+    }
+}
+
+#[test]
+fn conformance() {
+    assert_conformance(&mut Raqote, &[]);
+}
@@ -0,0 +1,75 @@
+//! Check that Raqote images read back what they were made and updated with.
+
+use raqote::DrawTarget;
+
+use kurbo::Rect;
+
+use piet::{Image, ImageFormat, InterpolationMode, RenderContext};
+use piet_raqote::RaqoteRenderContext;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+const CLEAR: [u8; 4] = [0, 0, 0, 0];
+const HALF_BLUE: [u8; 4] = [0, 0, 255, 128];
+
+#[test]
+fn update_image_reads_back() {
+    let mut draw_target = DrawTarget::new(1, 1);
+    let mut rc = RaqoteRenderContext::new(&mut draw_target);
+    let buf = [RED, RED, RED, RED, RED, RED].concat();
+    let mut image = rc
+        .make_image(3, 2, &buf, ImageFormat::RgbaSeparate)
+        .unwrap();
+    // Only the pixels for the dirty area are used.
+    let update = [GREEN, CLEAR, HALF_BLUE, HALF_BLUE].concat();
+    rc.update_image(
+        &mut image,
+        Rect::new(1.0, 0.5, 3.0, 1.0),
+        &update,
+        ImageFormat::RgbaSeparate,
+    )
+    .unwrap();
+    let pixels = image.to_raw_pixels(ImageFormat::RgbaSeparate).unwrap();
+    assert_eq!(pixels, [RED, GREEN, CLEAR, RED, RED, RED].concat());
+    let pixels = image.to_raw_pixels(ImageFormat::RgbaPremul).unwrap();
+    assert_eq!(&pixels[4..8], &GREEN[..]);
+    rc.finish().unwrap();
+}
+
+#[test]
+fn update_image_checks_the_area() {
+    let mut draw_target = DrawTarget::new(1, 1);
+    let mut rc = RaqoteRenderContext::new(&mut draw_target);
+    let mut image = rc
+        .make_image(1, 1, &RED, ImageFormat::RgbaSeparate)
+        .unwrap();
+    let outside = Rect::new(1.0, 0.0, 2.0, 1.0);
+    assert!(rc
+        .update_image(&mut image, outside, &GREEN, ImageFormat::RgbaSeparate)
+        .is_err());
+    let whole = Rect::new(0.0, 0.0, 1.0, 1.0);
+    assert!(rc
+        .update_image(&mut image, whole, &GREEN[..3], ImageFormat::RgbaSeparate)
+        .is_err());
+    let pixels = image.to_raw_pixels(ImageFormat::RgbaSeparate).unwrap();
+    assert_eq!(pixels, RED);
+    rc.finish().unwrap();
+}
+
+#[test]
+fn update_image_refreshes_scaled_down_draws() {
+    let mut draw_target = DrawTarget::new(1, 1);
+    let mut rc = RaqoteRenderContext::new(&mut draw_target);
+    let mut image = rc
+        .make_image(4, 1, &[RED; 4].concat(), ImageFormat::RgbaSeparate)
+        .unwrap();
+    let rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+    rc.draw_image(&image, rect, InterpolationMode::HighQuality);
+    let whole = Rect::new(0.0, 0.0, 4.0, 1.0);
+    let update = [GREEN; 4].concat();
+    rc.update_image(&mut image, whole, &update, ImageFormat::RgbaSeparate)
+        .unwrap();
+    rc.draw_image(&image, rect, InterpolationMode::HighQuality);
+    rc.finish().unwrap();
+    assert_eq!(draw_target.get_data(), &[0xff_00_ff_00]);
+}
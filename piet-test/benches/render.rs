@@ -0,0 +1,253 @@
+//! Rendering benchmarks for the enabled back-ends.
+//!
+//! Each benchmark draws a scene through the piet API, over and over into
+//! one target per back-end. Only the drawing is timed: creating the target,
+//! and resources such as images and fonts, happens before timing starts.
+//! Throughput is reported in the scene's operations, such as rectangles
+//! filled or images drawn, so back-ends can be compared per operation.
+
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Bencher, Criterion, ParameterizedBenchmark, Throughput};
+
+use kurbo::{Affine, BezPath, Rect};
+
+use piet::{
+    Error, FillRule, FontBuilder, ImageFormat, InterpolationMode, RenderContext, Text,
+    TextLayoutBuilder,
+};
+
+use piet_test::backend::{Backend, Session};
+use piet_test::TestPicture;
+
+const WIDTH: usize = 512;
+const HEIGHT: usize = 512;
+const PIX_SCALE: f64 = 1.0;
+
+/// A scene stressing one kind of operation.
+#[derive(Clone, Copy, Debug)]
+enum Scene {
+    /// Fill many small rectangles, with a new brush every few.
+    Rects(usize),
+    /// Stroke a single path made of many cubic Bézier segments.
+    BezierPath(usize),
+    /// Build and draw many short text layouts.
+    TextLayouts(usize),
+    /// Draw a large image over the whole target many times.
+    ImageBlits(usize),
+    /// Nest saves with a transform and clip at each level, then restore.
+    SaveRestore(usize),
+}
+
+impl Scene {
+    /// The name of the benchmark group for this scene.
+    fn name(self) -> String {
+        match self {
+            Scene::Rects(n) => format!("rects/{}", n),
+            Scene::BezierPath(n) => format!("bezier_path/{}", n),
+            Scene::TextLayouts(n) => format!("text_layouts/{}", n),
+            Scene::ImageBlits(n) => format!("image_blits/{}", n),
+            Scene::SaveRestore(n) => format!("save_restore/{}", n),
+        }
+    }
+
+    /// The number of operations the scene performs.
+    fn ops(self) -> usize {
+        match self {
+            Scene::Rects(n)
+            | Scene::BezierPath(n)
+            | Scene::TextLayouts(n)
+            | Scene::ImageBlits(n)
+            | Scene::SaveRestore(n) => n,
+        }
+    }
+
+    /// The back-ends to run the scene on.
+    ///
+    /// Text scenes are skipped on back-ends that don't draw text, where
+    /// they'd only time building layouts.
+    fn backends(self) -> Vec<Backend> {
+        let mut backends = Backend::all();
+        if let Scene::TextLayouts(_) = self {
+            backends.retain(|backend| backend.draws_text());
+        }
+        backends
+    }
+}
+
+impl Benchable for Scene {
+    fn bench<R: RenderContext>(&self, rc: &mut R, b: &mut Bencher) -> Result<(), Error> {
+        match *self {
+            Scene::Rects(n) => b.iter(|| {
+                let mut brush = rc.solid_brush(0x00_00_80_FF).expect("brush failed");
+                for i in 0..n {
+                    if i % 16 == 0 {
+                        brush = rc
+                            .solid_brush(0x00_00_80_C0 ^ ((i as u32) << 8))
+                            .expect("brush failed");
+                    }
+                    let x = (i * 7 % WIDTH) as f64;
+                    let y = (i * 13 % HEIGHT) as f64;
+                    rc.fill(Rect::new(x, y, x + 4.0, y + 4.0), &brush, FillRule::NonZero);
+                }
+            }),
+            Scene::BezierPath(n) => {
+                let mut path = BezPath::new();
+                path.moveto((0.0, HEIGHT as f64 / 2.0));
+                let step = WIDTH as f64 / n as f64;
+                for i in 0..n {
+                    let x = i as f64 * step;
+                    let dy = if i % 2 == 0 { 100.0 } else { -100.0 };
+                    let y = HEIGHT as f64 / 2.0;
+                    path.curveto(
+                        (x + step / 3.0, y + dy),
+                        (x + 2.0 * step / 3.0, y - dy),
+                        (x + step, y),
+                    );
+                }
+                let brush = rc.solid_brush(0x00_80_00_FF)?;
+                b.iter(|| rc.stroke(&path, &brush, 1.0, None));
+            }
+            Scene::TextLayouts(n) => {
+                let font = rc.text().new_font_by_name("sans-serif", 10.0)?.build()?;
+                let brush = rc.solid_brush(0x00_00_00_FF)?;
+                b.iter(|| {
+                    // Start from a blank target, so every iteration draws
+                    // the same thing.
+                    rc.clear(0xFF_FF_FF);
+                    for i in 0..n {
+                        let text = format!("label {}", i);
+                        let layout = rc
+                            .text()
+                            .new_text_layout(&font, &text)
+                            .and_then(|builder| builder.build())
+                            .expect("text layout failed");
+                        let x = (i * 53 % WIDTH) as f64;
+                        let y = (i * 17 % HEIGHT) as f64 + 10.0;
+                        rc.draw_text(&layout, (x, y), &brush);
+                    }
+                });
+            }
+            Scene::ImageBlits(n) => {
+                let data = image_data(1024, 1024);
+                let image = rc.make_image(1024, 1024, &data, ImageFormat::RgbaSeparate)?;
+                let rect = Rect::new(0.0, 0.0, WIDTH as f64, HEIGHT as f64);
+                b.iter(|| {
+                    for _ in 0..n {
+                        rc.draw_image(&image, rect, InterpolationMode::Bilinear);
+                    }
+                });
+            }
+            Scene::SaveRestore(n) => {
+                let brush = rc.solid_brush(0x80_00_00_40)?;
+                b.iter(|| {
+                    for i in 0..n {
+                        rc.save().expect("save failed");
+                        rc.transform(Affine::translate((0.5, 0.25)));
+                        let inset = (i % 64) as f64;
+                        rc.clip(
+                            Rect::new(inset, inset, WIDTH as f64, HEIGHT as f64),
+                            FillRule::NonZero,
+                        );
+                        rc.fill(Rect::new(0.0, 0.0, 8.0, 8.0), &brush, FillRule::NonZero);
+                    }
+                    for _ in 0..n {
+                        rc.restore().expect("restore failed");
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn image_data(width: usize, height: usize) -> Vec<u8> {
+    let mut result = vec![0; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let ix = (y * width + x) * 4;
+            result[ix + 0] = x as u8;
+            result[ix + 1] = y as u8;
+            result[ix + 2] = (x ^ y) as u8;
+            result[ix + 3] = 255;
+        }
+    }
+    result
+}
+
+/// Something that can be benchmarked by drawing it repeatedly.
+trait Benchable {
+    /// Set up any resources the drawing needs, then time the drawing with
+    /// `b`.
+    fn bench<R: RenderContext>(&self, rc: &mut R, b: &mut Bencher) -> Result<(), Error>;
+}
+
+impl Benchable for TestPicture {
+    fn bench<R: RenderContext>(&self, rc: &mut R, b: &mut Bencher) -> Result<(), Error> {
+        b.iter(|| self.draw(rc).expect("draw failed"));
+        Ok(())
+    }
+}
+
+/// A session that benchmarks its content on one render context.
+struct Timed<'a, D> {
+    bencher: &'a mut Bencher,
+    content: &'a D,
+}
+
+impl<'a, D: Benchable> Session for Timed<'a, D> {
+    fn run<R: RenderContext>(&mut self, rc: &mut R) -> Result<(), Error> {
+        rc.clear(0xFF_FF_FF);
+        self.content.bench(rc, self.bencher)
+    }
+}
+
+/// Benchmark drawing something on the given back-ends.
+fn bench_drawable<D>(c: &mut Criterion, name: &str, content: D, ops: usize, backends: Vec<Backend>)
+where
+    D: Benchable + 'static,
+{
+    c.bench(
+        name,
+        ParameterizedBenchmark::new(
+            "render",
+            move |b, &backend: &Backend| {
+                let mut timed = Timed {
+                    bencher: b,
+                    content: &content,
+                };
+                backend
+                    .run(WIDTH, HEIGHT, PIX_SCALE, &mut timed)
+                    .expect("render failed")
+            },
+            backends,
+        )
+        .throughput(move |_| Throughput::Elements(ops as u32)),
+    );
+}
+
+fn test_pictures(c: &mut Criterion) {
+    for picture in TestPicture::all() {
+        let name = format!("picture/{}", picture.name());
+        bench_drawable(c, &name, picture, 1, Backend::all());
+    }
+}
+
+fn stress_scenes(c: &mut Criterion) {
+    for &scene in &[
+        Scene::Rects(5000),
+        Scene::BezierPath(10_000),
+        Scene::TextLayouts(1000),
+        Scene::ImageBlits(20),
+        Scene::SaveRestore(500),
+    ] {
+        let backends = scene.backends();
+        if !backends.is_empty() {
+            bench_drawable(c, &scene.name(), scene, scene.ops(), backends);
+        }
+    }
+}
+
+criterion_group!(benches, test_pictures, stress_scenes);
+criterion_main!(benches);
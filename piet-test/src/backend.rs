@@ -0,0 +1,272 @@
+//! Rendering through the available back-ends into bitmaps.
+//!
+//! Each back-end is enabled by the cargo feature of the same name.
+
+use piet::{Error, RenderContext};
+
+use crate::TestPicture;
+
+/// Something that can be drawn with any back-end.
+pub trait Drawable {
+    fn draw<R: RenderContext>(&self, rc: &mut R) -> Result<(), Error>;
+}
+
+impl Drawable for TestPicture {
+    fn draw<R: RenderContext>(&self, rc: &mut R) -> Result<(), Error> {
+        TestPicture::draw(self, rc)
+    }
+}
+
+/// Something that works with a render context for as long as it likes,
+/// such as a benchmark drawing into the same target many times.
+pub trait Session {
+    fn run<R: RenderContext>(&mut self, rc: &mut R) -> Result<(), Error>;
+}
+
+/// A session that draws something once.
+struct DrawOnce<'a, D>(&'a D);
+
+impl<'a, D: Drawable> Session for DrawOnce<'a, D> {
+    fn run<R: RenderContext>(&mut self, rc: &mut R) -> Result<(), Error> {
+        self.0.draw(rc)
+    }
+}
+
+/// A rendered bitmap, in RGBA order with separate alpha.
+#[derive(Clone)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// A back-end that can render into a bitmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    #[cfg(feature = "cairo")]
+    Cairo,
+    #[cfg(feature = "raqote")]
+    Raqote,
+}
+
+impl Backend {
+    /// All the back-ends enabled in this build.
+    pub fn all() -> Vec<Backend> {
+        vec![
+            #[cfg(feature = "cairo")]
+            Backend::Cairo,
+            #[cfg(feature = "raqote")]
+            Backend::Raqote,
+        ]
+    }
+
+    /// Find an enabled back-end by its name.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::all().into_iter().find(|b| b.name() == name)
+    }
+
+    /// The name of the back-end, in lowercase.
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "cairo")]
+            Backend::Cairo => "cairo",
+            #[cfg(feature = "raqote")]
+            Backend::Raqote => "raqote",
+        }
+    }
+
+    /// Whether the back-end draws text, rather than ignoring it.
+    pub fn draws_text(self) -> bool {
+        match self {
+            #[cfg(feature = "cairo")]
+            Backend::Cairo => true,
+            #[cfg(feature = "raqote")]
+            Backend::Raqote => false,
+        }
+    }
+
+    /// Render into a new bitmap of the given size in pixels.
+    ///
+    /// Drawing happens in a coordinate space scaled by `pix_scale`, and the
+    /// render context is finished before reading back the pixels.
+    pub fn render(
+        self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        content: &impl Drawable,
+    ) -> Result<Bitmap, Error> {
+        let rgba_premul = self.render_premul(width, height, pix_scale, content)?;
+        let mut data = vec![0; width * height * 4];
+        piet::convert_from_rgba_premul(
+            &rgba_premul,
+            &mut data,
+            piet::ImageFormat::RgbaSeparate,
+            0,
+        )?;
+        Ok(Bitmap {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Render into a new bitmap, composited over an opaque background.
+    ///
+    /// This is like [`render`](#method.render), but the `background` RGB
+    /// color shows through wherever the content isn't opaque, including
+    /// where it clears the target.
+    pub fn render_over(
+        self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        background: u32,
+        content: &impl Drawable,
+    ) -> Result<Bitmap, Error> {
+        let rgba_premul = self.render_premul(width, height, pix_scale, content)?;
+        let mut rgb = vec![0; width * height * 3];
+        piet::convert_from_rgba_premul(&rgba_premul, &mut rgb, piet::ImageFormat::Rgb, background)?;
+        let mut data = Vec::with_capacity(width * height * 4);
+        for p in rgb.chunks(3) {
+            data.extend_from_slice(&[p[0], p[1], p[2], 255]);
+        }
+        Ok(Bitmap {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Run a session with a render context for a new target of the given
+    /// size in pixels.
+    ///
+    /// This sets up the target like [`render`](#method.render), and finishes
+    /// the render context after the session, but doesn't read back the
+    /// pixels.
+    pub fn run(
+        self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        session: &mut impl Session,
+    ) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "cairo")]
+            Backend::Cairo => cairo_back::run(width, height, pix_scale, session).map(|_| ()),
+            #[cfg(feature = "raqote")]
+            Backend::Raqote => raqote_back::run(width, height, pix_scale, session).map(|_| ()),
+        }
+    }
+
+    fn render_premul(
+        self,
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        content: &impl Drawable,
+    ) -> Result<Vec<u8>, Error> {
+        let mut session = DrawOnce(content);
+        match self {
+            #[cfg(feature = "cairo")]
+            Backend::Cairo => cairo_back::render(width, height, pix_scale, &mut session),
+            #[cfg(feature = "raqote")]
+            Backend::Raqote => raqote_back::render(width, height, pix_scale, &mut session),
+        }
+    }
+}
+
+#[cfg(feature = "cairo")]
+mod cairo_back {
+    use cairo::prelude::SurfaceExt;
+    use cairo::{Context, Format, ImageSurface};
+
+    use piet::{Error, ImageFormat, RenderContext};
+    use piet_cairo::CairoRenderContext;
+
+    use super::Session;
+
+    fn wrap_err(e: impl std::fmt::Debug) -> Error {
+        let e: Box<dyn std::error::Error + Send + Sync> = format!("Cairo error: {:?}", e).into();
+        e.into()
+    }
+
+    /// Run a session on a new surface, and return the surface.
+    pub fn run(
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        session: &mut impl Session,
+    ) -> Result<ImageSurface, Error> {
+        let surface =
+            ImageSurface::create(Format::ARgb32, width as i32, height as i32).map_err(wrap_err)?;
+        {
+            let mut cr = Context::new(&surface);
+            cr.scale(pix_scale, pix_scale);
+            let mut rc = CairoRenderContext::new(&mut cr);
+            session.run(&mut rc)?;
+            rc.finish()?;
+        }
+        Ok(surface)
+    }
+
+    /// Render to premultiplied RGBA.
+    pub fn render(
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        session: &mut impl Session,
+    ) -> Result<Vec<u8>, Error> {
+        let mut surface = run(width, height, pix_scale, session)?;
+        surface.flush();
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().map_err(wrap_err)?;
+        // Cairo stores premultiplied BGRA on little-endian machines.
+        let bgra = piet::packed_image_buf(width, height, stride, &data, ImageFormat::BgraPremul);
+        let mut result = vec![0; width * height * 4];
+        piet::convert_to_rgba_premul(&bgra, &mut result, ImageFormat::BgraPremul)?;
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "raqote")]
+mod raqote_back {
+    use kurbo::Affine;
+
+    use raqote_rs::DrawTarget;
+
+    use piet::{Error, RenderContext};
+    use piet_raqote::RaqoteRenderContext;
+
+    use super::Session;
+
+    /// Run a session on a new draw target, and return the draw target.
+    pub fn run(
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        session: &mut impl Session,
+    ) -> Result<DrawTarget, Error> {
+        let mut draw_target = DrawTarget::new(width as i32, height as i32);
+        {
+            let mut rc = RaqoteRenderContext::new(&mut draw_target);
+            rc.transform(Affine::scale(pix_scale));
+            session.run(&mut rc)?;
+            rc.finish()?;
+        }
+        Ok(draw_target)
+    }
+
+    /// Render to premultiplied RGBA.
+    pub fn render(
+        width: usize,
+        height: usize,
+        pix_scale: f64,
+        session: &mut impl Session,
+    ) -> Result<Vec<u8>, Error> {
+        let draw_target = run(width, height, pix_scale, session)?;
+        let mut result = vec![0; width * height * 4];
+        piet::argb32_to_rgba_premul(draw_target.get_data(), &mut result)?;
+        Ok(result)
+    }
+}
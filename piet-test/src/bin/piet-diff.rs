@@ -0,0 +1,69 @@
+//! Compare two PNG files perceptually.
+//!
+//! Usage: `piet-diff <expected.png> <actual.png> [diff.png] [--threshold T] [--include-aa]`
+//!
+//! Prints summary statistics, writes a highlighted diff image if a path is
+//! given, and exits with status 1 if any pixels differ.
+
+use std::path::Path;
+use std::process;
+
+use piet_test::diff::{perceptual_diff, DiffOptions};
+use piet_test::golden::{load_png, save_png};
+
+const USAGE: &str =
+    "usage: piet-diff <expected.png> <actual.png> [diff.png] [--threshold T] [--include-aa]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+fn main() {
+    let mut options = DiffOptions::default();
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => {
+                options.threshold = args
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or_else(|| fail("--threshold needs a number between 0 and 1"));
+            }
+            "--include-aa" => options.include_aa = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() < 2 || paths.len() > 3 {
+        fail(USAGE);
+    }
+
+    let expected = load_png(Path::new(&paths[0])).unwrap_or_else(|e| fail(&e));
+    let actual = load_png(Path::new(&paths[1])).unwrap_or_else(|e| fail(&e));
+    let diff = perceptual_diff(&expected, &actual, &options).unwrap_or_else(|| {
+        fail(&format!(
+            "size mismatch: {}x{} vs {}x{}",
+            expected.width, expected.height, actual.width, actual.height
+        ))
+    });
+    let stats = diff.stats;
+    println!(
+        "{} of {} pixels differ ({:.4}%)",
+        stats.differing_pixels,
+        stats.total_pixels,
+        100.0 * stats.differing_pixels as f64 / stats.total_pixels.max(1) as f64
+    );
+    println!("{} anti-aliased pixels", stats.antialiased_pixels);
+    println!("max delta {:.3}", stats.max_delta);
+    if let Some(diff_path) = paths.get(2) {
+        save_png(Path::new(diff_path), &diff.image).unwrap_or_else(|e| fail(&e));
+    }
+    if stats.differing_pixels > 0 {
+        process::exit(1);
+    }
+}
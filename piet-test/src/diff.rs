@@ -0,0 +1,322 @@
+//! Perceptual comparison of rendered bitmaps.
+//!
+//! This follows the approach of [pixelmatch]: color differences are measured
+//! in the YIQ color space, which tracks perceived differences much better
+//! than RGB, and differing pixels that look like anti-aliasing are counted
+//! separately. That makes it possible to compare renders from back-ends that
+//! rasterize edges slightly differently.
+//!
+//! [pixelmatch]: https://github.com/mapbox/pixelmatch
+
+use crate::backend::Bitmap;
+
+/// The largest possible YIQ delta between two colors.
+const MAX_YIQ_DELTA: f64 = 35215.0;
+
+/// Options for a perceptual diff.
+#[derive(Clone, Copy, Debug)]
+pub struct DiffOptions {
+    /// The matching threshold, from 0 to 1. Smaller is more sensitive.
+    pub threshold: f64,
+    /// Count anti-aliased pixels as differences.
+    pub include_aa: bool,
+    /// The opacity of unchanged pixels in the diff image, from 0 to 1.
+    pub alpha: f64,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            threshold: 0.1,
+            include_aa: false,
+            alpha: 0.1,
+        }
+    }
+}
+
+/// Summary statistics of a perceptual diff.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiffStats {
+    /// The number of pixels that differ noticeably.
+    pub differing_pixels: usize,
+    /// The number of differing pixels detected as anti-aliasing, which are
+    /// not counted in `differing_pixels` unless `include_aa` is set.
+    pub antialiased_pixels: usize,
+    /// The total number of pixels compared.
+    pub total_pixels: usize,
+    /// The largest difference found, on the same 0 to 1 scale as the threshold.
+    pub max_delta: f64,
+}
+
+/// The result of a perceptual diff.
+pub struct Diff {
+    pub stats: DiffStats,
+    /// Differences in red and anti-aliasing in yellow, over a faded
+    /// grayscale copy of the first image.
+    pub image: Bitmap,
+}
+
+/// Compare two bitmaps perceptually.
+///
+/// Returns `None` if the bitmaps have different sizes.
+pub fn perceptual_diff(a: &Bitmap, b: &Bitmap, options: &DiffOptions) -> Option<Diff> {
+    if a.width != b.width || a.height != b.height {
+        return None;
+    }
+    let (width, height) = (a.width, a.height);
+    let max_delta = MAX_YIQ_DELTA * options.threshold * options.threshold;
+    let mut stats = DiffStats {
+        total_pixels: width * height,
+        ..DiffStats::default()
+    };
+    let mut out = vec![0; a.data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let pos = (y * width + x) * 4;
+            let delta = color_delta(&a.data[pos..], &b.data[pos..], false);
+            stats.max_delta = stats.max_delta.max((delta.abs() / MAX_YIQ_DELTA).sqrt());
+            let color = if delta.abs() > max_delta {
+                if !options.include_aa && (antialiased(a, x, y, b) || antialiased(b, x, y, a)) {
+                    stats.antialiased_pixels += 1;
+                    [255, 255, 0]
+                } else {
+                    stats.differing_pixels += 1;
+                    [255, 0, 0]
+                }
+            } else {
+                let p = &a.data[pos..];
+                let luma = rgb_to_y(blend(p[0], p[3]), blend(p[1], p[3]), blend(p[2], p[3]));
+                let v = (255.0 + (luma - 255.0) * options.alpha) as u8;
+                [v, v, v]
+            };
+            out[pos..pos + 3].copy_from_slice(&color);
+            out[pos + 3] = 255;
+        }
+    }
+    Some(Diff {
+        stats,
+        image: Bitmap {
+            width,
+            height,
+            data: out,
+        },
+    })
+}
+
+/// Composite a channel with separate alpha over white.
+fn blend(c: u8, a: u8) -> f64 {
+    255.0 + (c as f64 - 255.0) * (a as f64 / 255.0)
+}
+
+fn rgb_to_y(r: f64, g: f64, b: f64) -> f64 {
+    r * 0.298_895_31 + g * 0.586_622_47 + b * 0.114_482_23
+}
+
+fn rgb_to_i(r: f64, g: f64, b: f64) -> f64 {
+    r * 0.595_977_99 - g * 0.274_176_10 - b * 0.321_801_89
+}
+
+fn rgb_to_q(r: f64, g: f64, b: f64) -> f64 {
+    r * 0.211_470_17 - g * 0.522_617_11 + b * 0.311_146_94
+}
+
+/// The squared YIQ distance between two pixels.
+///
+/// The sign is negative if the first pixel is brighter, which the
+/// anti-aliasing detection relies on. With `y_only`, only the difference in
+/// brightness is returned.
+fn color_delta(p1: &[u8], p2: &[u8], y_only: bool) -> f64 {
+    if p1[..4] == p2[..4] {
+        return 0.0;
+    }
+    let (r1, g1, b1) = (
+        blend(p1[0], p1[3]),
+        blend(p1[1], p1[3]),
+        blend(p1[2], p1[3]),
+    );
+    let (r2, g2, b2) = (
+        blend(p2[0], p2[3]),
+        blend(p2[1], p2[3]),
+        blend(p2[2], p2[3]),
+    );
+    let y1 = rgb_to_y(r1, g1, b1);
+    let y2 = rgb_to_y(r2, g2, b2);
+    let y = y1 - y2;
+    if y_only {
+        return y;
+    }
+    let i = rgb_to_i(r1, g1, b1) - rgb_to_i(r2, g2, b2);
+    let q = rgb_to_q(r1, g1, b1) - rgb_to_q(r2, g2, b2);
+    let delta = 0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q;
+    if y1 > y2 {
+        -delta
+    } else {
+        delta
+    }
+}
+
+/// The ranges of neighboring coordinates, clamped to the bitmap.
+fn neighborhood(bitmap: &Bitmap, x: usize, y: usize) -> (usize, usize, usize, usize) {
+    (
+        x.saturating_sub(1),
+        y.saturating_sub(1),
+        (x + 1).min(bitmap.width - 1),
+        (y + 1).min(bitmap.height - 1),
+    )
+}
+
+/// Whether a pixel looks like part of an anti-aliased edge.
+///
+/// Anti-aliased pixels sit between a darker and a brighter neighbor, each of
+/// which lies in a flat area in both images.
+fn antialiased(bitmap: &Bitmap, x: usize, y: usize, other: &Bitmap) -> bool {
+    let (x0, y0, x2, y2) = neighborhood(bitmap, x, y);
+    let pos = (y * bitmap.width + x) * 4;
+    let mut zeroes = if x == x0 || x == x2 || y == y0 || y == y2 {
+        1
+    } else {
+        0
+    };
+    let (mut min, mut max) = (0.0, 0.0);
+    let (mut min_pt, mut max_pt) = ((0, 0), (0, 0));
+    for ny in y0..=y2 {
+        for nx in x0..=x2 {
+            if nx == x && ny == y {
+                continue;
+            }
+            let npos = (ny * bitmap.width + nx) * 4;
+            let delta = color_delta(&bitmap.data[pos..], &bitmap.data[npos..], true);
+            if delta == 0.0 {
+                zeroes += 1;
+                if zeroes > 2 {
+                    return false;
+                }
+            } else if delta < min {
+                min = delta;
+                min_pt = (nx, ny);
+            } else if delta > max {
+                max = delta;
+                max_pt = (nx, ny);
+            }
+        }
+    }
+    if min == 0.0 || max == 0.0 {
+        return false;
+    }
+    (has_many_siblings(bitmap, min_pt) && has_many_siblings(other, min_pt))
+        || (has_many_siblings(bitmap, max_pt) && has_many_siblings(other, max_pt))
+}
+
+/// Whether a pixel has at least three neighbors of exactly the same color.
+fn has_many_siblings(bitmap: &Bitmap, (x, y): (usize, usize)) -> bool {
+    let (x0, y0, x2, y2) = neighborhood(bitmap, x, y);
+    let pos = (y * bitmap.width + x) * 4;
+    let mut zeroes = if x == x0 || x == x2 || y == y0 || y == y2 {
+        1
+    } else {
+        0
+    };
+    for ny in y0..=y2 {
+        for nx in x0..=x2 {
+            if nx == x && ny == y {
+                continue;
+            }
+            let npos = (ny * bitmap.width + nx) * 4;
+            if bitmap.data[pos..pos + 4] == bitmap.data[npos..npos + 4] {
+                zeroes += 1;
+                if zeroes > 2 {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    /// An 8x8 bitmap, colored by a function of the pixel position.
+    fn bitmap(color: impl Fn(usize, usize) -> [u8; 4]) -> Bitmap {
+        let mut data = Vec::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                data.extend_from_slice(&color(x, y));
+            }
+        }
+        Bitmap {
+            width: 8,
+            height: 8,
+            data,
+        }
+    }
+
+    /// Black on the left half, white on the right.
+    fn edge(x: usize, _: usize) -> [u8; 4] {
+        if x < 4 {
+            BLACK
+        } else {
+            WHITE
+        }
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let a = bitmap(edge);
+        let diff = perceptual_diff(&a, &a.clone(), &DiffOptions::default()).unwrap();
+        assert_eq!(diff.stats.differing_pixels, 0);
+        assert_eq!(diff.stats.antialiased_pixels, 0);
+        assert_eq!(diff.stats.total_pixels, 64);
+        assert_eq!(diff.stats.max_delta, 0.0);
+    }
+
+    #[test]
+    fn single_changed_pixel_differs() {
+        let a = bitmap(|_, _| WHITE);
+        let b = bitmap(|x, y| if (x, y) == (3, 5) { BLACK } else { WHITE });
+        let diff = perceptual_diff(&a, &b, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.stats.differing_pixels, 1);
+        assert_eq!(diff.stats.antialiased_pixels, 0);
+        assert!(diff.stats.max_delta > 0.9);
+        let pos = (5 * 8 + 3) * 4;
+        assert_eq!(diff.image.data[pos..pos + 4], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn antialiased_edge_is_not_counted() {
+        let a = bitmap(edge);
+        // The same edge, with a column of gray where it's anti-aliased.
+        let b = bitmap(|x, y| {
+            if x == 4 {
+                [128, 128, 128, 255]
+            } else {
+                edge(x, y)
+            }
+        });
+        let diff = perceptual_diff(&a, &b, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.stats.differing_pixels, 0);
+        assert_eq!(diff.stats.antialiased_pixels, 8);
+
+        let options = DiffOptions {
+            include_aa: true,
+            ..DiffOptions::default()
+        };
+        let diff = perceptual_diff(&a, &b, &options).unwrap();
+        assert_eq!(diff.stats.differing_pixels, 8);
+    }
+
+    #[test]
+    fn different_sizes_are_not_compared() {
+        let a = bitmap(edge);
+        let b = Bitmap {
+            width: 4,
+            height: 16,
+            data: a.data.clone(),
+        };
+        assert!(perceptual_diff(&a, &b, &DiffOptions::default()).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Comparing rendered bitmaps against reference images.
+//!
+//! Reference images are PNG files stored per back-end, as
+//! `<reference_dir>/<backend>/<name>.png`. When a render doesn't match, the
+//! actual render and an image highlighting the differences are written to
+//! the output directory for inspection.
+//!
+//! Setting the `PIET_BLESS` environment variable replaces the references
+//! with the current renders. Without it, a missing reference is a mismatch,
+//! so a new test picture needs its references blessed and checked in.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use piet::ImageFormat;
+
+use crate::backend::Bitmap;
+use crate::diff::{perceptual_diff, DiffOptions};
+
+/// How pixels of a render are compared with its reference.
+#[derive(Clone, Copy, Debug)]
+pub enum Metric {
+    /// Pixels are equal if no channel differs by more than the given amount.
+    Channel(u8),
+    /// Pixels are equal if they look the same; see the [`diff`] module.
+    ///
+    /// [`diff`]: ../diff/index.html
+    Perceptual(DiffOptions),
+}
+
+/// How much a render may differ from its reference and still match.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    pub metric: Metric,
+    /// The number of pixels that may differ under the metric.
+    pub pixels: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        Tolerance {
+            metric: Metric::Perceptual(DiffOptions::default()),
+            pixels: 0,
+        }
+    }
+}
+
+/// The result of comparing two bitmaps of the same size.
+pub struct Comparison {
+    /// The number of pixels differing by more than the channel tolerance.
+    pub differing_pixels: usize,
+    /// The largest difference in any channel of any pixel.
+    pub max_channel_delta: u8,
+    /// An image showing differing pixels in red over a faded copy of the
+    /// expected image.
+    pub diff: Bitmap,
+}
+
+/// Compare two bitmaps pixel by pixel.
+///
+/// Returns `None` if the bitmaps have different sizes.
+pub fn compare(expected: &Bitmap, actual: &Bitmap, channel_tolerance: u8) -> Option<Comparison> {
+    if expected.width != actual.width || expected.height != actual.height {
+        return None;
+    }
+    let mut differing_pixels = 0;
+    let mut max_channel_delta = 0;
+    let mut diff = vec![0; expected.data.len()];
+    for ((e, a), d) in expected
+        .data
+        .chunks(4)
+        .zip(actual.data.chunks(4))
+        .zip(diff.chunks_mut(4))
+    {
+        let delta = (0..4)
+            .map(|i| (e[i] as i16 - a[i] as i16).abs() as u8)
+            .max()
+            .unwrap();
+        max_channel_delta = max_channel_delta.max(delta);
+        if delta > channel_tolerance {
+            differing_pixels += 1;
+            d.copy_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // Fade the expected pixel towards white so differences stand out.
+            let luma = (e[0] as u32 * 3 + e[1] as u32 * 6 + e[2] as u32) / 10;
+            let alpha = e[3] as u32;
+            let faded = (255 - (255 - luma) * alpha / 255 / 4) as u8;
+            d.copy_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+    Some(Comparison {
+        differing_pixels,
+        max_channel_delta,
+        diff: Bitmap {
+            width: expected.width,
+            height: expected.height,
+            data: diff,
+        },
+    })
+}
+
+/// A render that didn't match its reference image.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub backend: String,
+    pub name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}: {}", self.backend, self.name, self.reason)
+    }
+}
+
+/// Checks renders against a directory of reference images.
+pub struct Golden {
+    pub reference_dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub tolerance: Tolerance,
+    /// Overwrite the references with the renders instead of comparing.
+    pub bless: bool,
+}
+
+impl Golden {
+    /// Create a checker with the default tolerance.
+    ///
+    /// Blessing is enabled if the `PIET_BLESS` environment variable is set.
+    pub fn new(reference_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Golden {
+        Golden {
+            reference_dir: reference_dir.into(),
+            output_dir: output_dir.into(),
+            tolerance: Tolerance::default(),
+            bless: std::env::var_os("PIET_BLESS").is_some(),
+        }
+    }
+
+    /// Check a render against its reference image.
+    pub fn check(&self, backend: &str, name: &str, actual: &Bitmap) -> Result<(), Mismatch> {
+        let mismatch = |reason: String| Mismatch {
+            backend: backend.to_owned(),
+            name: name.to_owned(),
+            reason,
+        };
+        let reference_path = self
+            .reference_dir
+            .join(backend)
+            .join(format!("{}.png", name));
+        if self.bless {
+            return save_png(&reference_path, actual).map_err(mismatch);
+        }
+        let output_dir = self.output_dir.join(backend);
+        let actual_path = output_dir.join(format!("{}-actual.png", name));
+        if !reference_path.exists() {
+            save_png(&actual_path, actual).map_err(mismatch)?;
+            return Err(mismatch(format!(
+                "no reference image {}; render written to {}, run with PIET_BLESS=1 to accept it",
+                reference_path.display(),
+                actual_path.display()
+            )));
+        }
+        let expected = load_png(&reference_path).map_err(mismatch)?;
+        let result = match self.tolerance.metric {
+            Metric::Channel(channel) => compare(&expected, actual, channel).map(|c| {
+                let summary = format!("max channel delta {}", c.max_channel_delta);
+                (c.differing_pixels, summary, c.diff)
+            }),
+            Metric::Perceptual(ref options) => {
+                perceptual_diff(&expected, actual, options).map(|d| {
+                    let summary = format!(
+                        "max delta {:.3}, {} anti-aliased",
+                        d.stats.max_delta, d.stats.antialiased_pixels
+                    );
+                    (d.stats.differing_pixels, summary, d.image)
+                })
+            }
+        };
+        match result {
+            None => {
+                save_png(&actual_path, actual).map_err(mismatch)?;
+                Err(mismatch(format!(
+                    "size is {}x{}, reference is {}x{}; render written to {}",
+                    actual.width,
+                    actual.height,
+                    expected.width,
+                    expected.height,
+                    actual_path.display()
+                )))
+            }
+            Some((differing_pixels, summary, diff)) if differing_pixels > self.tolerance.pixels => {
+                let diff_path = output_dir.join(format!("{}-diff.png", name));
+                save_png(&actual_path, actual).map_err(mismatch)?;
+                save_png(&diff_path, &diff).map_err(mismatch)?;
+                Err(mismatch(format!(
+                    "{} pixels differ ({}); diff written to {}",
+                    differing_pixels,
+                    summary,
+                    diff_path.display()
+                )))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Load a PNG file as RGBA with separate alpha.
+pub fn load_png(path: &Path) -> Result<Bitmap, String> {
+    let load_err = |e: &dyn fmt::Display| format!("can't load {}: {}", path.display(), e);
+    let encoded = fs::read(path).map_err(|e| load_err(&e))?;
+    let decoded = piet::decode_image(&encoded).map_err(|e| load_err(&e))?;
+    let data = match decoded.format {
+        ImageFormat::Grayscale => decoded
+            .buf
+            .iter()
+            .flat_map(|&v| vec![v, v, v, 255])
+            .collect(),
+        ImageFormat::Rgb => decoded
+            .buf
+            .chunks(3)
+            .flat_map(|p| vec![p[0], p[1], p[2], 255])
+            .collect(),
+        _ => decoded.buf,
+    };
+    Ok(Bitmap {
+        width: decoded.width,
+        height: decoded.height,
+        data,
+    })
+}
+
+/// Save a bitmap as a PNG file, creating its directory if needed.
+pub fn save_png(path: &Path, bitmap: &Bitmap) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("can't create {}: {}", dir.display(), e))?;
+    }
+    image_rs::save_buffer(
+        path,
+        &bitmap.data,
+        bitmap.width as u32,
+        bitmap.height as u32,
+        image_rs::ColorType::RGBA(8),
+    )
+    .map_err(|e| format!("can't save {}: {}", path.display(), e))
+}
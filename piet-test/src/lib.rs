@@ -2,7 +2,15 @@
 
 // Right now, this is just code to generate sample images.
 
-use piet::{Error, RenderContext};
+use std::fmt;
+
+use piet::{new_error, Error, ErrorKind, RenderContext};
+
+pub mod backend;
+pub mod diff;
+#[cfg(feature = "image")]
+pub mod golden;
+
 mod picture_0;
 mod picture_1;
 mod picture_2;
@@ -15,24 +23,223 @@ use crate::picture_2::draw as draw_picture_2;
 use crate::picture_3::draw as draw_picture_3;
 use crate::picture_4::draw as draw_picture_4;
 
-/// Draw a test picture, by number.
+/// A drawing feature a test picture relies on.
+///
+/// Back-ends that don't support a capability can skip the pictures that
+/// need it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Text,
+    Images,
+    Gradients,
+    Clip,
+    StrokeStyles,
+    Transforms,
+}
+
+/// A test picture in the registry.
+///
+/// Test pictures can only be obtained from the registry, through
+/// [`all`](#method.all), [`by_number`](#method.by_number) or
+/// [`find`](#method.find).
+#[derive(Clone, Copy, Debug)]
+pub struct TestPicture {
+    number: usize,
+    name: &'static str,
+    description: &'static str,
+    size: (f64, f64),
+    capabilities: &'static [Capability],
+}
+
+/// All the test pictures, in order of number.
 ///
 /// There are a few test pictures here now, and hopefully it will grow into
 /// a full suite, suitable for both benchmarking and correctness testing.
+const PICTURES: &[TestPicture] = &[
+    TestPicture {
+        number: 0,
+        name: "assortment",
+        description: "A wide assortment of graphics",
+        size: (200.0, 100.0),
+        capabilities: &[
+            Capability::Text,
+            Capability::Images,
+            Capability::Clip,
+            Capability::Transforms,
+        ],
+    },
+    TestPicture {
+        number: 1,
+        name: "cubic-bezier",
+        description: "A cubic Bézier curve with its control points and handles",
+        size: (200.0, 100.0),
+        capabilities: &[],
+    },
+    TestPicture {
+        number: 2,
+        name: "images",
+        description: "Images in each format and interpolation mode",
+        size: (200.0, 100.0),
+        capabilities: &[Capability::Images],
+    },
+    TestPicture {
+        number: 3,
+        name: "stroke-styles",
+        description: "Line caps, joins and dashes",
+        size: (200.0, 100.0),
+        capabilities: &[Capability::StrokeStyles, Capability::Transforms],
+    },
+    TestPicture {
+        number: 4,
+        name: "gradients",
+        description: "Linear and radial gradients",
+        size: (200.0, 100.0),
+        capabilities: &[Capability::Gradients],
+    },
+];
+
+/// The error for a test picture that doesn't exist.
+#[derive(Debug)]
+pub struct UnknownPicture(pub String);
+
+impl fmt::Display for UnknownPicture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Don't have test picture {} yet. Why don't you make it?",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownPicture {}
+
+impl TestPicture {
+    /// Iterate over all the test pictures.
+    pub fn all() -> impl Iterator<Item = TestPicture> {
+        PICTURES.iter().cloned()
+    }
+
+    /// Find a test picture by number.
+    pub fn by_number(number: usize) -> Result<TestPicture, UnknownPicture> {
+        PICTURES
+            .get(number)
+            .cloned()
+            .ok_or_else(|| UnknownPicture(number.to_string()))
+    }
+
+    /// Find a test picture by name, or by number given as a string, either
+    /// alone or as `picture-N`.
+    pub fn find(name: &str) -> Result<TestPicture, UnknownPicture> {
+        let number = if name.starts_with("picture-") {
+            &name["picture-".len()..]
+        } else {
+            name
+        };
+        if let Ok(number) = number.parse() {
+            return TestPicture::by_number(number);
+        }
+        PICTURES
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .ok_or_else(|| UnknownPicture(name.to_owned()))
+    }
+
+    /// The number of the picture.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// A short identifier, usable in file names.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// A one-line description of what the picture shows.
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// The size of the area the picture is drawn in, in drawing units.
+    pub fn size(&self) -> (f64, f64) {
+        self.size
+    }
+
+    /// The capabilities the picture relies on.
+    pub fn capabilities(&self) -> &'static [Capability] {
+        self.capabilities
+    }
+
+    /// Whether the picture relies on a capability.
+    pub fn requires(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Draw the picture.
+    ///
+    /// The picture doesn't clear the target, so it can be drawn over any
+    /// background.
+    pub fn draw(&self, rc: &mut impl RenderContext) -> Result<(), Error> {
+        match self.number {
+            0 => draw_picture_0(rc),
+            1 => draw_picture_1(rc),
+            2 => draw_picture_2(rc),
+            3 => draw_picture_3(rc),
+            4 => draw_picture_4(rc),
+            // Pictures only come from the registry, which has no others.
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Draw a test picture, by number, on a white background.
+///
+/// Unknown pictures are reported as `ErrorKind::InvalidInput`.
 pub fn draw_test_picture(rc: &mut impl RenderContext, number: usize) -> Result<(), Error> {
-    match number {
-        0 => draw_picture_0(rc),
-        1 => draw_picture_1(rc),
-        2 => draw_picture_2(rc),
-        3 => draw_picture_3(rc),
-        4 => draw_picture_4(rc),
-        _ => {
-            eprintln!(
-                "Don't have test picture {} yet. Why don't you make it?",
-                number
-            );
-            // TODO: error?
-            Ok(())
+    match TestPicture::by_number(number) {
+        Ok(picture) => {
+            rc.clear(0xFF_FF_FF);
+            picture.draw(rc)
+        }
+        Err(e) => Err(new_error(ErrorKind::InvalidInput).with_context(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_is_in_order() {
+        for (i, picture) in TestPicture::all().enumerate() {
+            assert_eq!(picture.number(), i);
         }
     }
+
+    #[test]
+    fn by_number_finds_pictures() {
+        assert_eq!(TestPicture::by_number(0).unwrap().name(), "assortment");
+        assert_eq!(TestPicture::by_number(3).unwrap().name(), "stroke-styles");
+        let count = TestPicture::all().count();
+        assert_eq!(
+            TestPicture::by_number(count).unwrap_err().0,
+            count.to_string()
+        );
+    }
+
+    #[test]
+    fn find_accepts_names_and_numbers() {
+        assert_eq!(TestPicture::find("images").unwrap().number(), 2);
+        assert_eq!(TestPicture::find("2").unwrap().number(), 2);
+        assert_eq!(TestPicture::find("picture-2").unwrap().number(), 2);
+    }
+
+    #[test]
+    fn find_reports_unknown_pictures() {
+        assert_eq!(TestPicture::find("nope").unwrap_err().0, "nope");
+        assert_eq!(TestPicture::find("picture-99").unwrap_err().0, "99");
+        assert_eq!(TestPicture::find("picture-").unwrap_err().0, "picture-");
+        assert!(TestPicture::find("").is_err());
+    }
 }
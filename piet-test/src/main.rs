@@ -0,0 +1,266 @@
+//! Render test pictures to files.
+//!
+//! Run with `--help` for the options.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use piet_test::backend::{Backend, Bitmap};
+use piet_test::golden::save_png;
+use piet_test::TestPicture;
+
+const USAGE: &str = "usage: piet-test [options] <picture>
+       piet-test [options] --all
+       piet-test --list
+
+Render a test picture, given by number, name or as `picture-N`.
+
+options:
+    -b, --backend NAME      back-end to render with (default: first enabled)
+    -s, --size WxH          size in pixels (default: the picture's size, scaled)
+    -p, --pix-scale SCALE   pixels per drawing unit (default: 2)
+    -B, --background RGB    composite over an opaque background, as hex RRGGBB
+                            (default: transparent)
+    -f, --format FORMAT     `png`, or `rgba` for raw separate-alpha bytes (default: png)
+    -o, --output PATH       output file (default: temp-<backend>.<format>)
+        --all               render every picture into a contact sheet, for
+                            every enabled back-end unless one is chosen
+        --list              list the test pictures";
+
+/// Gap between pictures in a contact sheet, in pixels.
+const SHEET_GAP: usize = 8;
+/// Number of pictures per row in a contact sheet.
+const SHEET_COLUMNS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Png,
+    Rgba,
+}
+
+struct Options {
+    backends: Vec<Backend>,
+    size: Option<(usize, usize)>,
+    pix_scale: f64,
+    background: Option<u32>,
+    format: Format,
+    output: Option<PathBuf>,
+    picture: Option<TestPicture>,
+    all: bool,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+fn parse_size(arg: &str) -> Option<(usize, usize)> {
+    let mut parts = arg.splitn(2, 'x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        backends: Backend::all().into_iter().take(1).collect(),
+        size: None,
+        pix_scale: 2.0,
+        background: None,
+        format: Format::Png,
+        output: None,
+        picture: None,
+        all: false,
+    };
+    let mut backend_chosen = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "-b" | "--backend" => {
+                let name = value(&arg);
+                let backend = Backend::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("unknown or disabled back-end {}", name)));
+                options.backends = vec![backend];
+                backend_chosen = true;
+            }
+            "-s" | "--size" => {
+                let size =
+                    parse_size(&value(&arg)).unwrap_or_else(|| fail("size must be given as WxH"));
+                options.size = Some(size);
+            }
+            "-p" | "--pix-scale" => {
+                options.pix_scale = value(&arg)
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|&scale| scale.is_finite() && scale > 0.0)
+                    .unwrap_or_else(|| fail("pix-scale must be a positive number"));
+            }
+            "-B" | "--background" => {
+                let rgb = value(&arg);
+                let rgb = u32::from_str_radix(rgb.trim_start_matches('#'), 16)
+                    .ok()
+                    .filter(|&rgb| rgb <= 0xff_ff_ff)
+                    .unwrap_or_else(|| fail("background must be given as RRGGBB"));
+                options.background = Some(rgb);
+            }
+            "-f" | "--format" => {
+                options.format = match value(&arg).as_str() {
+                    "png" => Format::Png,
+                    "rgba" => Format::Rgba,
+                    other => fail(&format!("unknown format {}", other)),
+                }
+            }
+            "-o" | "--output" => options.output = Some(value(&arg).into()),
+            "--all" => options.all = true,
+            "--list" => {
+                for picture in TestPicture::all() {
+                    println!(
+                        "{:>2}  {:<16}{}",
+                        picture.number(),
+                        picture.name(),
+                        picture.description()
+                    );
+                }
+                process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if options.picture.is_none() => {
+                let picture = TestPicture::find(&arg).unwrap_or_else(|e| fail(&e.to_string()));
+                options.picture = Some(picture);
+            }
+            _ => fail(USAGE),
+        }
+    }
+    if options.all == options.picture.is_some() {
+        fail(USAGE);
+    }
+    if options.backends.is_empty() {
+        fail("no back-ends are enabled in this build");
+    }
+    if options.all && !backend_chosen {
+        options.backends = Backend::all();
+    }
+    options
+}
+
+/// The output path for a back-end's render.
+///
+/// Without an explicit path, this is `<stem>-<backend>.<format>`. When
+/// rendering with several back-ends, the back-end name is added to the
+/// explicit path too.
+fn output_path(options: &Options, stem: &str, backend: Backend) -> PathBuf {
+    let extension = match options.format {
+        Format::Png => "png",
+        Format::Rgba => "rgba",
+    };
+    match options.output {
+        Some(ref path) if options.backends.len() == 1 => path.clone(),
+        Some(ref path) => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = path
+                .extension()
+                .map(|e| e.to_string_lossy())
+                .unwrap_or_else(|| extension.into());
+            path.with_file_name(format!("{}-{}.{}", stem, backend.name(), extension))
+        }
+        None => PathBuf::from(format!("{}-{}.{}", stem, backend.name(), extension)),
+    }
+}
+
+/// Lay bitmaps out in a grid, in cells fitting the largest.
+fn contact_sheet(bitmaps: &[Bitmap]) -> Bitmap {
+    let width = bitmaps.iter().map(|b| b.width).max().unwrap_or(0);
+    let height = bitmaps.iter().map(|b| b.height).max().unwrap_or(0);
+    let columns = SHEET_COLUMNS.min(bitmaps.len());
+    let rows = (bitmaps.len() + columns - 1) / columns;
+    let sheet_width = columns * width + (columns + 1) * SHEET_GAP;
+    let sheet_height = rows * height + (rows + 1) * SHEET_GAP;
+    // A light gray background, so transparent pictures stay visible.
+    let mut data = Vec::with_capacity(sheet_width * sheet_height * 4);
+    for _ in 0..sheet_width * sheet_height {
+        data.extend_from_slice(&[0xc0, 0xc0, 0xc0, 0xff]);
+    }
+    for (i, bitmap) in bitmaps.iter().enumerate() {
+        let x0 = SHEET_GAP + (i % columns) * (width + SHEET_GAP);
+        let y0 = SHEET_GAP + (i / columns) * (height + SHEET_GAP);
+        let row_bytes = bitmap.width * 4;
+        for y in 0..bitmap.height {
+            let src = y * row_bytes;
+            let dst = ((y0 + y) * sheet_width + x0) * 4;
+            data[dst..dst + row_bytes].copy_from_slice(&bitmap.data[src..src + row_bytes]);
+        }
+    }
+    Bitmap {
+        width: sheet_width,
+        height: sheet_height,
+        data,
+    }
+}
+
+fn write_output(path: &Path, format: Format, bitmap: &Bitmap) -> Result<(), String> {
+    match format {
+        Format::Png => save_png(path, bitmap),
+        Format::Rgba => fs::write(path, &bitmap.data)
+            .map_err(|e| format!("can't write {}: {}", path.display(), e)),
+    }
+}
+
+fn render(options: &Options, backend: Backend, picture: &TestPicture) -> Bitmap {
+    let (width, height) = options.size.unwrap_or_else(|| {
+        let (width, height) = picture.size();
+        (
+            (width * options.pix_scale).ceil() as usize,
+            (height * options.pix_scale).ceil() as usize,
+        )
+    });
+    let pix_scale = options.pix_scale;
+    // The background is composited under the render, so it shows through
+    // wherever the picture leaves the target transparent.
+    match options.background {
+        Some(rgb) => backend.render_over(width, height, pix_scale, rgb, picture),
+        None => backend.render(width, height, pix_scale, picture),
+    }
+    .unwrap_or_else(|e| {
+        fail(&format!(
+            "{}: error rendering {}: {}",
+            backend.name(),
+            picture.name(),
+            e
+        ))
+    })
+}
+
+fn main() {
+    let options = parse_args();
+    for &backend in &options.backends {
+        let (bitmap, path) = if options.all {
+            let bitmaps: Vec<_> = TestPicture::all()
+                .map(|picture| render(&options, backend, &picture))
+                .collect();
+            let sheet = contact_sheet(&bitmaps);
+            (sheet, output_path(&options, "contact-sheet", backend))
+        } else {
+            let bitmap = render(&options, backend, options.picture.as_ref().unwrap());
+            (bitmap, output_path(&options, "temp", backend))
+        };
+        write_output(&path, options.format, &bitmap).unwrap_or_else(|e| fail(&e));
+        println!(
+            "{}: wrote {}x{} to {}",
+            backend.name(),
+            bitmap.width,
+            bitmap.height,
+            path.display()
+        );
+    }
+}
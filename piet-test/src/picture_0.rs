@@ -8,7 +8,6 @@ use piet::{
 };
 
 pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
-    rc.clear(0xFF_FF_FF);
     let brush = rc.solid_brush(0x00_00_80_FF)?;
     rc.stroke(Line::new((10.0, 10.0), (100.0, 50.0)), &brush, 1.0, None);
 
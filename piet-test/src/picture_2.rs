@@ -1,10 +1,8 @@
 //! A bunch of image test cases.
 
-use piet::{Error, ImageFormat, InterpolationMode, RenderContext};
+use piet::{premul, Error, ImageFormat, InterpolationMode, RenderContext};
 
 pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
-    rc.clear(0xFF_FF_FF);
-
     let mut y = 5.0;
     for &mode in &[
         InterpolationMode::NearestNeighbor,
@@ -45,10 +43,6 @@ fn make_image_data(width: usize, height: usize, format: ImageFormat) -> Vec<u8>
                     result[ix + 3] = a;
                 }
                 ImageFormat::RgbaPremul => {
-                    fn premul(x: u8, a: u8) -> u8 {
-                        let y = (x as u16) * (a as u16);
-                        ((y + (y >> 8) + 0x80) >> 8) as u8
-                    }
                     result[ix + 0] = premul(r, a);
                     result[ix + 1] = premul(g, a);
                     result[ix + 2] = premul(b, a);
@@ -5,8 +5,6 @@ use kurbo::{Affine, BezPath, Line};
 use piet::{Error, LineCap, LineJoin, RenderContext, StrokeStyle};
 
 pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
-    rc.clear(0xFF_FF_FF);
-
     let mut path = BezPath::new();
     path.moveto((0.0, 0.0));
     path.lineto((20.0, 0.0));
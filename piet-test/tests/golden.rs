@@ -0,0 +1,40 @@
+//! Render every test picture through each enabled back-end and compare
+//! against the reference images in `reference/`.
+//!
+//! A picture without a reference fails. Run with `PIET_BLESS=1` to create
+//! the references, or to update them after an intended rendering change,
+//! then check them in.
+
+use std::path::{Path, PathBuf};
+
+use piet_test::backend::Backend;
+use piet_test::golden::Golden;
+use piet_test::TestPicture;
+
+const PIX_SCALE: f64 = 2.0;
+
+#[test]
+fn test_pictures_match_references() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // Mismatches are written next to the build output.
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.join("../target"));
+    let golden = Golden::new(root.join("reference"), target_dir.join("golden"));
+    let mut failures = Vec::new();
+    for backend in Backend::all() {
+        for picture in TestPicture::all() {
+            let width = (picture.size().0 * PIX_SCALE).ceil() as usize;
+            let height = (picture.size().1 * PIX_SCALE).ceil() as usize;
+            match backend.render(width, height, PIX_SCALE, &picture) {
+                Ok(bitmap) => {
+                    if let Err(e) = golden.check(backend.name(), picture.name(), &bitmap) {
+                        failures.push(e.to_string());
+                    }
+                }
+                Err(e) => failures.push(format!("{}/{}: {}", backend.name(), picture.name(), e)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -1,7 +1,9 @@
 //! The Web Canvas backend for the Piet 2D graphics abstraction.
 
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt;
+use std::ops::{Deref, DerefMut};
 
 use js_sys::{Float64Array, Reflect};
 use wasm_bindgen::{Clamped, JsCast, JsValue};
@@ -13,25 +15,104 @@ use web_sys::{
 use kurbo::{Affine, PathEl, Rect, Shape, Vec2};
 
 use piet::{
-    Error, Font, FontBuilder, Gradient, GradientStop, ImageFormat, InterpolationMode, LineCap,
-    LineJoin, RenderContext, RoundInto, StrokeStyle, Text, TextLayout, TextLayoutBuilder,
+    affine_inverse, capture_pixel_area, check_image_buf, convert_from_rgba_premul,
+    convert_to_rgba_premul, dither_to_rgba_separate, image_dirty_area, image_src_area,
+    is_drawable_rect, new_error, packed_image_buf, premul, unpremul, DrawImageOptions, Error,
+    ErrorKind, Font, FontBuilder, Gradient, GradientStop, Image, ImageFormat, InterpolationMode,
+    LineCap, LineJoin, Offscreen, RenderContext, RoundInto, StrokeStyle, Text, TextLayout,
+    TextLayoutBuilder,
 };
 
 pub struct WebRenderContext<'a> {
-    ctx: &'a mut CanvasRenderingContext2d,
+    ctx: ContextRef<'a>,
     /// Used for creating image bitmaps and possibly other resources.
-    window: &'a Window,
+    window: Cow<'a, Window>,
+    /// The first error from an operation that can't return one, reported
+    /// by `status` and `finish`.
     err: Result<(), Error>,
 }
 
 impl<'a> WebRenderContext<'a> {
     pub fn new(ctx: &'a mut CanvasRenderingContext2d, window: &'a Window) -> WebRenderContext<'a> {
         WebRenderContext {
-            ctx,
-            window,
+            ctx: ContextRef::Borrowed(ctx),
+            window: Cow::Borrowed(window),
             err: Ok(()),
         }
     }
+
+    /// Create a canvas element to draw an image into.
+    fn make_canvas(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> (HtmlCanvasElement, CanvasRenderingContext2d) {
+        let document = self.window.document().unwrap();
+        let element = document.create_element("canvas").unwrap();
+        let canvas = element.dyn_into::<HtmlCanvasElement>().unwrap();
+        canvas.set_width(width);
+        canvas.set_height(height);
+        let context = canvas_context(&canvas);
+        (canvas, context)
+    }
+
+    /// Set up image smoothing for an interpolation mode.
+    ///
+    /// The canvas saves these settings, so this is meant to be used within
+    /// `with_save`.
+    fn set_interp(&mut self, interp: InterpolationMode) -> Result<(), Error> {
+        let quality = match interp {
+            InterpolationMode::NearestNeighbor => {
+                self.ctx.set_image_smoothing_enabled(false);
+                return Ok(());
+            }
+            InterpolationMode::Bilinear => "low",
+            InterpolationMode::Bicubic => "medium",
+            InterpolationMode::HighQuality => "high",
+        };
+        self.ctx.set_image_smoothing_enabled(true);
+        // Not every browser has this, so it's set without a binding.
+        Reflect::set(
+            self.ctx.as_ref(),
+            &JsValue::from("imageSmoothingQuality"),
+            &JsValue::from(quality),
+        )
+        .wrap()?;
+        Ok(())
+    }
+
+    /// Record an error from the named operation, unless one is already pending.
+    fn record_err(&mut self, op: &'static str, e: Error) {
+        if self.err.is_ok() {
+            self.err = Err(e.with_op(op));
+        }
+    }
+}
+
+/// The canvas context, which is owned by offscreen targets.
+enum ContextRef<'a> {
+    Borrowed(&'a mut CanvasRenderingContext2d),
+    Owned(CanvasRenderingContext2d),
+}
+
+impl<'a> Deref for ContextRef<'a> {
+    type Target = CanvasRenderingContext2d;
+
+    fn deref(&self) -> &CanvasRenderingContext2d {
+        match *self {
+            ContextRef::Borrowed(ref ctx) => ctx,
+            ContextRef::Owned(ref ctx) => ctx,
+        }
+    }
+}
+
+impl<'a> DerefMut for ContextRef<'a> {
+    fn deref_mut(&mut self) -> &mut CanvasRenderingContext2d {
+        match *self {
+            ContextRef::Borrowed(ref mut ctx) => ctx,
+            ContextRef::Owned(ref mut ctx) => ctx,
+        }
+    }
 }
 
 pub enum Brush {
@@ -67,6 +148,17 @@ pub struct WebImage {
     inner: HtmlCanvasElement,
     width: u32,
     height: u32,
+    format: ImageFormat,
+    /// A copy of the area last drawn by `draw_image_area`, with the area.
+    area_copy: RefCell<Option<(Rect, HtmlCanvasElement)>>,
+}
+
+/// A target for drawing into an image, with its own canvas element.
+pub struct WebOffscreen {
+    canvas: HtmlCanvasElement,
+    width: u32,
+    height: u32,
+    rc: WebRenderContext<'static>,
 }
 
 /// https://developer.mozilla.org/en-US/docs/Web/CSS/font-style
@@ -78,8 +170,10 @@ enum FontStyle {
     Oblique(Option<f64>),
 }
 
+/// A JavaScript error, formatted eagerly because `JsValue` can't be sent
+/// between threads.
 #[derive(Debug)]
-struct WrappedJs(JsValue);
+struct WrappedJs(String);
 
 trait WrapError<T> {
     fn wrap(self) -> Result<T, Error>;
@@ -89,7 +183,7 @@ impl std::error::Error for WrappedJs {}
 
 impl fmt::Display for WrappedJs {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Canvas error: {:?}", self.0)
+        write!(f, "Canvas error: {}", self.0)
     }
 }
 
@@ -98,7 +192,8 @@ impl fmt::Display for WrappedJs {
 impl<T> WrapError<T> for Result<T, JsValue> {
     fn wrap(self) -> Result<T, Error> {
         self.map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(WrappedJs(e));
+            let e: Box<dyn std::error::Error + Send + Sync> =
+                Box::new(WrappedJs(format!("{:?}", e)));
             e.into()
         })
     }
@@ -137,6 +232,7 @@ impl<'a> RenderContext for WebRenderContext<'a> {
     type TextLayout = WebTextLayout;
 
     type Image = WebImage;
+    type Offscreen = WebOffscreen;
 
     fn status(&mut self) -> Result<(), Error> {
         std::mem::replace(&mut self.err, Ok(()))
@@ -156,7 +252,7 @@ impl<'a> RenderContext for WebRenderContext<'a> {
                 let (x0, y0) = (linear.start.x, linear.start.y);
                 let (x1, y1) = (linear.end.x, linear.end.y);
                 let mut lg = self.ctx.create_linear_gradient(x0, y0, x1, y1);
-                set_gradient_stops(&mut lg, &linear.stops);
+                set_gradient_stops(&mut lg, &linear.stops)?;
                 Ok(Brush::Gradient(lg))
             }
             Gradient::Radial(radial) => {
@@ -167,7 +263,7 @@ impl<'a> RenderContext for WebRenderContext<'a> {
                     .ctx
                     .create_radial_gradient(xc + xo, yc + yo, 0.0, xc, yc, r)
                     .wrap()?;
-                set_gradient_stops(&mut rg, &radial.stops);
+                set_gradient_stops(&mut rg, &radial.stops)?;
                 Ok(Brush::Gradient(rg))
             }
         }
@@ -194,7 +290,10 @@ impl<'a> RenderContext for WebRenderContext<'a> {
         style: Option<&StrokeStyle>,
     ) {
         self.set_path(shape);
-        self.set_stroke(width.round_into(), style);
+        if let Err(e) = self.set_stroke(width.round_into(), style) {
+            self.record_err("stroke", e);
+            return;
+        }
         self.set_brush(brush, false);
         self.ctx.stroke();
     }
@@ -213,7 +312,7 @@ impl<'a> RenderContext for WebRenderContext<'a> {
         self.set_brush(brush, true);
         let pos = pos.round_into();
         if let Err(e) = self.ctx.fill_text(&layout.text, pos.x, pos.y).wrap() {
-            self.err = Err(e);
+            self.record_err("draw_text", e);
         }
     }
 
@@ -233,96 +332,335 @@ impl<'a> RenderContext for WebRenderContext<'a> {
 
     fn transform(&mut self, transform: Affine) {
         let a = transform.as_coeffs();
-        let _ = self.ctx.transform(a[0], a[1], a[2], a[3], a[4], a[5]);
+        if let Err(e) = self
+            .ctx
+            .transform(a[0], a[1], a[2], a[3], a[4], a[5])
+            .wrap()
+        {
+            self.record_err("transform", e);
+        }
     }
 
-    fn make_image(
+    fn make_image_with_stride(
         &mut self,
         width: usize,
         height: usize,
+        stride: usize,
         buf: &[u8],
         format: ImageFormat,
     ) -> Result<Self::Image, Error> {
-        let document = self.window.document().unwrap();
-        let element = document.create_element("canvas").unwrap();
-        let canvas = element.dyn_into::<HtmlCanvasElement>().unwrap();
-        canvas.set_width(width as u32);
-        canvas.set_height(height as u32);
-        let mut buf = match format {
-            // Discussion topic: if buf were mut here, we could probably avoid this clone.
-            // See https://github.com/rustwasm/wasm-bindgen/issues/1005 for an issue that might
-            // also resolve the need to clone.
-            ImageFormat::RgbaSeparate => buf.to_vec(),
-            ImageFormat::RgbaPremul => {
-                fn unpremul(x: u8, a: u8) -> u8 {
-                    if a == 0 {
-                        0
-                    } else {
-                        let y = (x as u32 * 255 + (a as u32 / 2)) / (a as u32);
-                        y.min(255) as u8
-                    }
-                }
-                let mut new_buf = vec![0; width * height * 4];
-                for i in 0..width * height {
-                    let a = buf[i * 4 + 3];
-                    new_buf[i * 4 + 0] = unpremul(buf[i * 4 + 0], a);
-                    new_buf[i * 4 + 1] = unpremul(buf[i * 4 + 1], a);
-                    new_buf[i * 4 + 2] = unpremul(buf[i * 4 + 2], a);
-                    new_buf[i * 4 + 3] = a;
-                }
-                new_buf
-            }
-            ImageFormat::Rgb => {
-                let mut new_buf = vec![0; width * height * 4];
-                for i in 0..width * height {
-                    new_buf[i * 4 + 0] = buf[i * 3 + 0];
-                    new_buf[i * 4 + 1] = buf[i * 3 + 1];
-                    new_buf[i * 4 + 2] = buf[i * 3 + 2];
-                    new_buf[i * 4 + 3] = 255;
-                }
-                new_buf
-            }
-            _ => Vec::new(),
-        };
+        if format.bits_per_channel() > 8 {
+            // Images are stored with 8 bits per channel, so dither down.
+            let buf = dither_to_rgba_separate(width, height, stride, buf, format)?;
+            return self.make_image(width, height, &buf, ImageFormat::RgbaSeparate);
+        }
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        check_image_buf(width, height, stride, buf, format)?;
+        let buf = packed_image_buf(width, height, stride, buf, format);
+        let (canvas, context) = self.make_canvas(width as u32, height as u32);
+        let mut buf = to_rgba_separate(buf, width * height, format)?;
         let image_data =
             ImageData::new_with_u8_clamped_array(Clamped(&mut buf), width as u32).wrap()?;
-        let context = canvas
-            .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap();
         context.put_image_data(&image_data, 0.0, 0.0).wrap()?;
         Ok(WebImage {
             inner: canvas,
             width: width as u32,
             height: height as u32,
+            format,
+            area_copy: RefCell::new(None),
         })
     }
 
+    fn update_image(
+        &mut self,
+        image: &mut Self::Image,
+        dirty_rect: impl Into<Rect>,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        let area = image_dirty_area(
+            dirty_rect.into(),
+            image.width as usize,
+            image.height as usize,
+        )?;
+        let (width, height) = (area.width() as usize, area.height() as usize);
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let src_stride = width * format.bytes_per_pixel();
+        if format.bits_per_channel() > 8 {
+            let buf = dither_to_rgba_separate(width, height, src_stride, buf, format)?;
+            return self.update_image(image, area, &buf, ImageFormat::RgbaSeparate);
+        }
+        check_image_buf(width, height, src_stride, buf, format)?;
+        let buf = &buf[..src_stride * height];
+        let mut buf = to_rgba_separate(Cow::from(buf), width * height, format)?;
+        let image_data =
+            ImageData::new_with_u8_clamped_array(Clamped(&mut buf), width as u32).wrap()?;
+        *image.area_copy.get_mut() = None;
+        canvas_context(&image.inner)
+            .put_image_data(&image_data, area.x0, area.y0)
+            .wrap()
+    }
+
     fn draw_image(
         &mut self,
         image: &Self::Image,
         rect: impl Into<Rect>,
-        _interp: InterpolationMode,
+        interp: InterpolationMode,
+    ) {
+        self.draw_image_for(
+            "draw_image",
+            image,
+            rect.into(),
+            interp,
+            &DrawImageOptions::new(),
+        );
+    }
+
+    fn draw_image_with_options(
+        &mut self,
+        image: &Self::Image,
+        rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        self.draw_image_for(
+            "draw_image_with_options",
+            image,
+            rect.into(),
+            interp,
+            options,
+        );
+    }
+
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
     ) {
+        let src_rect = src_rect.into();
+        let dst_rect = dst_rect.into();
+        let area = match image_src_area(src_rect, image.width as usize, image.height as usize) {
+            Some(area) if is_drawable_rect(dst_rect) => area,
+            _ => return,
+        };
+        let (src_rect, dst_rect) = trim_to_area(src_rect, dst_rect, area);
         let result = self.with_save(|rc| {
-            let rect = rect.into();
-            let _ = rc.ctx.translate(rect.x0, rect.y0);
-            let _ = rc.ctx.scale(
-                rect.width() / (image.width as f64),
-                rect.height() / (image.height as f64),
-            );
+            rc.set_interp(interp)?;
+            let (canvas, origin) = match interp {
+                // Without filtering, nothing outside the area is sampled.
+                InterpolationMode::NearestNeighbor => (image.inner.clone(), (0.0, 0.0)),
+                // Filtering may use pixels outside the source rectangle, so
+                // draw from a copy of the area, where the edges are clamped.
+                _ => (image.area_copy(rc, area)?, (area.x0, area.y0)),
+            };
             rc.ctx
-                .draw_image_with_html_canvas_element(&image.inner, 0.0, 0.0)
+                .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    &canvas,
+                    src_rect.x0 - origin.0,
+                    src_rect.y0 - origin.1,
+                    src_rect.width(),
+                    src_rect.height(),
+                    dst_rect.x0,
+                    dst_rect.y0,
+                    dst_rect.width(),
+                    dst_rect.height(),
+                )
                 .wrap()
         });
         if let Err(e) = result {
-            self.err = Err(e);
+            self.record_err("draw_image_area", e);
+        }
+    }
+
+    fn draw_mask(&mut self, mask: &Self::Image, rect: impl Into<Rect>, brush: &Self::Brush) {
+        let rect = rect.into();
+        if !is_drawable_rect(rect) {
+            return;
+        }
+        let result = self.with_save(|rc| {
+            // Composite the brush onto a copy of the mask with "source-in",
+            // mapping the rectangle onto the copy so gradients line up.
+            let (canvas, context) = rc.make_canvas(mask.width, mask.height);
+            context
+                .draw_image_with_html_canvas_element(&mask.inner, 0.0, 0.0)
+                .wrap()?;
+            context.set_global_composite_operation("source-in").wrap()?;
+            context
+                .scale(
+                    mask.width as f64 / rect.width(),
+                    mask.height as f64 / rect.height(),
+                )
+                .wrap()?;
+            context.translate(-rect.x0, -rect.y0).wrap()?;
+            context.set_fill_style(&brush_style(brush));
+            context.fill_rect(rect.x0, rect.y0, rect.width(), rect.height());
+            rc.set_interp(InterpolationMode::Bilinear)?;
+            rc.ctx
+                .draw_image_with_html_canvas_element_and_dw_and_dh(
+                    &canvas,
+                    rect.x0,
+                    rect.y0,
+                    rect.width(),
+                    rect.height(),
+                )
+                .wrap()
+        });
+        if let Err(e) = result {
+            self.record_err("draw_mask", e);
+        }
+    }
+
+    fn create_offscreen(&mut self, width: usize, height: usize) -> Result<Self::Offscreen, Error> {
+        if width == 0
+            || height == 0
+            || width > u32::max_value() as usize
+            || height > u32::max_value() as usize
+        {
+            return Err(new_error(ErrorKind::InvalidImageSize)
+                .with_context(format!("can't make a {}x{} canvas", width, height)));
+        }
+        let (width, height) = (width as u32, height as u32);
+        let (canvas, context) = self.make_canvas(width, height);
+        Ok(WebOffscreen {
+            canvas,
+            width,
+            height,
+            rc: WebRenderContext {
+                ctx: ContextRef::Owned(context),
+                window: Cow::Owned((*self.window).clone()),
+                err: Ok(()),
+            },
+        })
+    }
+
+    fn capture_image_area(&mut self, rect: impl Into<Rect>) -> Result<Self::Image, Error> {
+        let area = capture_pixel_area(rect.into())?;
+        let (width, height) = (area.width() as u32, area.height() as u32);
+        // This ignores the transform, and reads outside the canvas as transparent.
+        let image_data = self
+            .ctx
+            .get_image_data(area.x0, area.y0, area.width(), area.height())
+            .wrap()?;
+        let (canvas, context) = self.make_canvas(width, height);
+        context.put_image_data(&image_data, 0.0, 0.0).wrap()?;
+        Ok(WebImage {
+            inner: canvas,
+            width,
+            height,
+            format: ImageFormat::RgbaPremul,
+            area_copy: RefCell::new(None),
+        })
+    }
+}
+
+impl WebImage {
+    /// A canvas holding a copy of an area of the image, at its origin.
+    ///
+    /// The copy is kept for drawing the same area again, until the image
+    /// is updated.
+    fn area_copy(&self, rc: &WebRenderContext, area: Rect) -> Result<HtmlCanvasElement, Error> {
+        let mut area_copy = self.area_copy.borrow_mut();
+        if let Some((copied, ref canvas)) = *area_copy {
+            if (copied.x0, copied.y0, copied.x1, copied.y1) == (area.x0, area.y0, area.x1, area.y1)
+            {
+                return Ok(canvas.clone());
+            }
         }
+        let (canvas, context) = rc.make_canvas(area.width() as u32, area.height() as u32);
+        context
+            .draw_image_with_html_canvas_element(&self.inner, -area.x0, -area.y0)
+            .wrap()?;
+        *area_copy = Some((area, canvas.clone()));
+        Ok(canvas)
     }
 }
 
+/// Trim a source rectangle to an area of the image, and the destination
+/// rectangle in proportion, since the canvas can't draw from outside it.
+fn trim_to_area(src_rect: Rect, dst_rect: Rect, area: Rect) -> (Rect, Rect) {
+    let scale_x = dst_rect.width() / src_rect.width();
+    let scale_y = dst_rect.height() / src_rect.height();
+    let x0 = src_rect.x0.max(area.x0).min(area.x1);
+    let y0 = src_rect.y0.max(area.y0).min(area.y1);
+    let x1 = src_rect.x1.max(area.x0).min(area.x1);
+    let y1 = src_rect.y1.max(area.y0).min(area.y1);
+    let to_dst = |x: f64, y: f64| {
+        (
+            dst_rect.x0 + (x - src_rect.x0) * scale_x,
+            dst_rect.y0 + (y - src_rect.y0) * scale_y,
+        )
+    };
+    let (dst_x0, dst_y0) = to_dst(x0, y0);
+    let (dst_x1, dst_y1) = to_dst(x1, y1);
+    (
+        Rect::new(x0, y0, x1, y1),
+        Rect::new(dst_x0, dst_y0, dst_x1, dst_y1),
+    )
+}
+
+/// The 2D context of a canvas element made for an image.
+fn canvas_context(canvas: &HtmlCanvasElement) -> CanvasRenderingContext2d {
+    canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap()
+}
+
+/// Convert tightly packed pixels to the separate-alpha RGBA of `ImageData`.
+fn to_rgba_separate(
+    buf: Cow<[u8]>,
+    n_pixels: usize,
+    format: ImageFormat,
+) -> Result<Vec<u8>, Error> {
+    let buf = match format {
+        // Discussion topic: if buf were mut here, we could probably avoid this clone.
+        // See https://github.com/rustwasm/wasm-bindgen/issues/1005 for an issue that might
+        // also resolve the need to clone.
+        ImageFormat::RgbaSeparate => buf.into_owned(),
+        ImageFormat::BgraSeparate => {
+            let mut new_buf = vec![0; n_pixels * 4];
+            for i in 0..n_pixels {
+                new_buf[i * 4 + 0] = buf[i * 4 + 2];
+                new_buf[i * 4 + 1] = buf[i * 4 + 1];
+                new_buf[i * 4 + 2] = buf[i * 4 + 0];
+                new_buf[i * 4 + 3] = buf[i * 4 + 3];
+            }
+            new_buf
+        }
+        ImageFormat::Rgb => {
+            let mut new_buf = vec![0; n_pixels * 4];
+            for i in 0..n_pixels {
+                new_buf[i * 4 + 0] = buf[i * 3 + 0];
+                new_buf[i * 4 + 1] = buf[i * 3 + 1];
+                new_buf[i * 4 + 2] = buf[i * 3 + 2];
+                new_buf[i * 4 + 3] = 255;
+            }
+            new_buf
+        }
+        _ => {
+            // Everything else goes through premultiplied RGBA.
+            let mut new_buf = vec![0; n_pixels * 4];
+            convert_to_rgba_premul(&buf, &mut new_buf, format)?;
+            for i in 0..n_pixels {
+                let a = new_buf[i * 4 + 3];
+                new_buf[i * 4 + 0] = unpremul(new_buf[i * 4 + 0], a);
+                new_buf[i * 4 + 1] = unpremul(new_buf[i * 4 + 1], a);
+                new_buf[i * 4 + 2] = unpremul(new_buf[i * 4 + 2], a);
+            }
+            new_buf
+        }
+    };
+    Ok(buf)
+}
+
 fn format_color(rgba: u32) -> String {
     let rgb = rgba >> 8;
     let a = rgba & 0xff;
@@ -339,10 +677,73 @@ fn format_color(rgba: u32) -> String {
     }
 }
 
-fn set_gradient_stops(dst: &mut CanvasGradient, src: &[GradientStop]) {
+/// The fill or stroke style for a brush.
+fn brush_style(brush: &Brush) -> JsValue {
+    match *brush {
+        Brush::Solid(rgba) => JsValue::from_str(&format_color(rgba)),
+        Brush::Gradient(ref gradient) => JsValue::from(gradient),
+    }
+}
+
+fn set_gradient_stops(dst: &mut CanvasGradient, src: &[GradientStop]) -> Result<(), Error> {
     for stop in src {
-        // TODO: maybe get error?
-        let _ = dst.add_color_stop(stop.pos, &format_color(stop.rgba));
+        dst.add_color_stop(stop.pos, &format_color(stop.rgba))
+            .wrap()?;
+    }
+    Ok(())
+}
+
+impl Image for WebImage {
+    fn size(&self) -> (usize, usize) {
+        (self.width as usize, self.height as usize)
+    }
+
+    fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    fn to_raw_pixels(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
+        if let ImageFormat::_NonExhaustive = format {
+            return Err(new_error(ErrorKind::NotSupported));
+        }
+        let image_data = canvas_context(&self.inner)
+            .get_image_data(0.0, 0.0, self.width as f64, self.height as f64)
+            .wrap()?;
+        let mut rgba = image_data.data().0;
+        if format == ImageFormat::RgbaSeparate {
+            return Ok(rgba);
+        }
+        for p in rgba.chunks_mut(4) {
+            let a = p[3];
+            p[0] = premul(p[0], a);
+            p[1] = premul(p[1], a);
+            p[2] = premul(p[2], a);
+        }
+        let n_pixels = (self.width * self.height) as usize;
+        let mut buf = vec![0; n_pixels * format.bytes_per_pixel()];
+        // As documented on Image, formats without alpha go over black.
+        convert_from_rgba_premul(&rgba, &mut buf, format, 0x00_00_00)?;
+        Ok(buf)
+    }
+}
+
+impl Offscreen for WebOffscreen {
+    type Image = WebImage;
+    type Context = WebRenderContext<'static>;
+
+    fn render_context(&mut self) -> &mut Self::Context {
+        &mut self.rc
+    }
+
+    fn into_image(mut self) -> Result<Self::Image, Error> {
+        self.rc.finish()?;
+        Ok(WebImage {
+            inner: self.canvas,
+            width: self.width,
+            height: self.height,
+            format: ImageFormat::RgbaPremul,
+            area_copy: RefCell::new(None),
+        })
     }
 }
 
@@ -384,27 +785,53 @@ impl<'a> Text for WebRenderContext<'a> {
 }
 
 impl<'a> WebRenderContext<'a> {
+    /// Draw an image with options, recording errors for the named operation.
+    fn draw_image_for(
+        &mut self,
+        op: &'static str,
+        image: &WebImage,
+        rect: Rect,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    ) {
+        // Nothing is drawn through a transform without an inverse, or into a
+        // rectangle without area.
+        if affine_inverse(options.transform()).is_none() || !is_drawable_rect(rect) {
+            return;
+        }
+        let result = self.with_save(|rc| {
+            if let Some(clip) = options.clip() {
+                rc.clip(clip.clone(), piet::FillRule::NonZero);
+            }
+            rc.transform(options.transform());
+            rc.set_interp(interp)?;
+            rc.ctx.set_global_alpha(options.opacity());
+            rc.ctx.translate(rect.x0, rect.y0).wrap()?;
+            rc.ctx
+                .scale(
+                    rect.width() / (image.width as f64),
+                    rect.height() / (image.height as f64),
+                )
+                .wrap()?;
+            rc.ctx
+                .draw_image_with_html_canvas_element(&image.inner, 0.0, 0.0)
+                .wrap()
+        });
+        if let Err(e) = result {
+            self.record_err(op, e);
+        }
+    }
+
     /// Set the source pattern to the brush.
     ///
     /// Web canvas is super stateful, and we're trying to have more retained stuff.
     /// This is part of the impedance matching.
     fn set_brush(&mut self, brush: &Brush, is_fill: bool) {
-        match *brush {
-            Brush::Solid(rgba) => {
-                let color_str = format_color(rgba);
-                if is_fill {
-                    self.ctx.set_fill_style(&JsValue::from_str(&color_str));
-                } else {
-                    self.ctx.set_stroke_style(&JsValue::from_str(&color_str));
-                }
-            }
-            Brush::Gradient(ref gradient) => {
-                if is_fill {
-                    self.ctx.set_fill_style(&JsValue::from(gradient));
-                } else {
-                    self.ctx.set_stroke_style(&JsValue::from(gradient));
-                }
-            }
+        let style = brush_style(brush);
+        if is_fill {
+            self.ctx.set_fill_style(&style);
+        } else {
+            self.ctx.set_stroke_style(&style);
         }
     }
 
@@ -412,7 +839,7 @@ impl<'a> WebRenderContext<'a> {
     ///
     /// TODO(performance): this is probably expensive enough it makes sense
     /// to at least store the last version and only reset if it's changed.
-    fn set_stroke(&mut self, width: f64, style: Option<&StrokeStyle>) {
+    fn set_stroke(&mut self, width: f64, style: Option<&StrokeStyle>) -> Result<(), Error> {
         self.ctx.set_line_width(width);
 
         let line_join = style
@@ -445,8 +872,9 @@ impl<'a> WebRenderContext<'a> {
             })
             .unwrap_or((Float64Array::new_with_length(0), 0.0));
 
-        self.ctx.set_line_dash(dash_segs.as_ref()).unwrap();
+        self.ctx.set_line_dash(dash_segs.as_ref()).wrap()?;
         self.ctx.set_line_dash_offset(dash_offset);
+        Ok(())
     }
 
     fn set_path(&mut self, shape: impl Shape) {
@@ -1,6 +1,6 @@
 //! Conversions of fundamental numeric and geometric types.
 
-use kurbo::Vec2;
+use kurbo::{Affine, Vec2};
 
 /// This is our own implementation of a "lossy From" trait, representing
 /// a conversion that can have precision loss. It is essentially adapted
@@ -71,3 +71,24 @@ impl<T> RoundFrom<T> for T {
         x
     }
 }
+
+/// The inverse of an affine transform.
+///
+/// Returns `None` when the transform has no inverse, because it collapses
+/// the plane onto a line or point, or when a coefficient isn't finite.
+pub fn affine_inverse(transform: Affine) -> Option<Affine> {
+    let [a, b, c, d, e, f] = transform.as_coeffs();
+    let det = a * d - b * c;
+    if det == 0.0 || !det.is_finite() || !e.is_finite() || !f.is_finite() {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some(Affine::new([
+        d * inv_det,
+        -b * inv_det,
+        -c * inv_det,
+        a * inv_det,
+        (c * f - d * e) * inv_det,
+        (b * e - a * f) * inv_det,
+    ]))
+}
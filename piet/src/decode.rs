@@ -0,0 +1,149 @@
+//! Decoding encoded images, such as PNG files, into render context images.
+
+#[cfg(feature = "image")]
+use image::DynamicImage;
+
+use crate::{new_error, Error, ErrorKind, ImageFormat, RenderContext};
+
+/// The pixels of a decoded image, tightly packed.
+pub struct DecodedImage {
+    pub width: usize,
+    pub height: usize,
+    pub buf: Vec<u8>,
+    /// `Grayscale`, `Rgb` or `RgbaSeparate`.
+    pub format: ImageFormat,
+}
+
+/// Decode an encoded image into pixels.
+///
+/// PNG, JPEG, GIF and WebP are supported; the format is detected from the
+/// data. For animated images, only the first frame is decoded. The pixels
+/// are kept in the closest format to the encoded color type, so grayscale
+/// images are `Grayscale` and images without alpha are `Rgb`. Data that
+/// can't be decoded is reported as `ErrorKind::InvalidInput`.
+///
+/// This requires the `image` feature; without it, the error is
+/// `ErrorKind::MissingFeature("image")`.
+#[cfg(feature = "image")]
+pub fn decode_image(encoded: &[u8]) -> Result<DecodedImage, Error> {
+    let image = image::load_from_memory(encoded).map_err(|e| {
+        new_error(ErrorKind::InvalidInput).with_context(format!("can't decode image: {}", e))
+    })?;
+    let (width, height, buf, format) = match image {
+        DynamicImage::ImageLuma8(buf) => {
+            let (width, height) = buf.dimensions();
+            (width, height, buf.into_raw(), ImageFormat::Grayscale)
+        }
+        DynamicImage::ImageRgb8(buf) => {
+            let (width, height) = buf.dimensions();
+            (width, height, buf.into_raw(), ImageFormat::Rgb)
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            let (width, height) = buf.dimensions();
+            (width, height, buf.into_raw(), ImageFormat::RgbaSeparate)
+        }
+        // There's no grayscale format with alpha, so expand to RGBA.
+        image => {
+            let buf = image.to_rgba();
+            let (width, height) = buf.dimensions();
+            (width, height, buf.into_raw(), ImageFormat::RgbaSeparate)
+        }
+    };
+    Ok(DecodedImage {
+        width: width as usize,
+        height: height as usize,
+        buf,
+        format,
+    })
+}
+
+/// Decode an encoded image into pixels.
+///
+/// This requires the `image` feature; without it, the error is
+/// `ErrorKind::MissingFeature("image")`.
+#[cfg(not(feature = "image"))]
+pub fn decode_image(encoded: &[u8]) -> Result<DecodedImage, Error> {
+    let _ = encoded;
+    Err(new_error(ErrorKind::MissingFeature("image")))
+}
+
+/// Decode an encoded image and make it into an image for the context.
+///
+/// The image is decoded as by [`decode_image`](fn.decode_image.html), and
+/// the same errors are reported.
+pub fn make_image_from_encoded<R: RenderContext>(
+    rc: &mut R,
+    encoded: &[u8],
+) -> Result<R::Image, Error> {
+    let decoded = decode_image(encoded)?;
+    rc.make_image(decoded.width, decoded.height, &decoded.buf, decoded.format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "image")]
+    fn decode(encoded: &[u8]) -> (usize, usize, Vec<u8>, ImageFormat) {
+        let decoded = decode_image(encoded).unwrap();
+        (decoded.width, decoded.height, decoded.buf, decoded.format)
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn decodes_each_color_type() {
+        assert_eq!(
+            decode(include_bytes!("../testdata/gray.png")),
+            (2, 1, vec![0, 255], ImageFormat::Grayscale)
+        );
+        assert_eq!(
+            decode(include_bytes!("../testdata/rgb.png")),
+            (2, 1, vec![255, 0, 0, 0, 0, 255], ImageFormat::Rgb)
+        );
+        assert_eq!(
+            decode(include_bytes!("../testdata/rgba.png")),
+            (
+                2,
+                1,
+                vec![255, 0, 0, 255, 0, 255, 0, 128],
+                ImageFormat::RgbaSeparate
+            )
+        );
+        assert_eq!(
+            decode(include_bytes!("../testdata/gray-alpha.png")),
+            (
+                2,
+                1,
+                vec![255, 255, 255, 255, 0, 0, 0, 64],
+                ImageFormat::RgbaSeparate
+            )
+        );
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn garbage_is_invalid_input() {
+        let truncated = &include_bytes!("../testdata/rgb.png")[..40];
+        for &encoded in &[&b"not an image"[..], &[][..], truncated] {
+            match decode_image(encoded) {
+                Err(ref e) => match *e.kind() {
+                    ErrorKind::InvalidInput => (),
+                    ref kind => panic!("unexpected error kind {:?}", kind),
+                },
+                Ok(_) => panic!("garbage was decoded"),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "image"))]
+    #[test]
+    fn decoding_needs_the_feature() {
+        match decode_image(include_bytes!("../testdata/gray.png")) {
+            Err(ref e) => match *e.kind() {
+                ErrorKind::MissingFeature("image") => (),
+                ref kind => panic!("unexpected error kind {:?}", kind),
+            },
+            Ok(_) => panic!("decoded without the image feature"),
+        }
+    }
+}
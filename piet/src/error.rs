@@ -3,43 +3,128 @@
 use std::fmt;
 
 /// An error that can occur while rendering 2D graphics.
+///
+/// Errors are `Send` and `Sync`, so they can be passed between threads.
 #[derive(Debug)]
-pub struct Error(Box<ErrorKind>);
+pub struct Error(Box<ErrorInner>);
+
+#[derive(Debug)]
+struct ErrorInner {
+    kind: ErrorKind,
+    /// The drawing operation that failed, if known.
+    op: Option<&'static str>,
+    /// A description of what went wrong, beyond the kind.
+    context: Option<String>,
+}
 
 #[derive(Debug)]
 pub enum ErrorKind {
+    /// An argument is invalid, such as an area outside an image, or an
+    /// image buffer that is empty or doesn't hold the image's pixels.
     InvalidInput,
+    /// An image is larger than the back-end can make.
+    InvalidImageSize,
     NotSupported,
+    /// The operation needs a cargo feature that isn't enabled.
+    MissingFeature(&'static str),
+    /// A font couldn't be found or loaded.
+    FontLoadingFailed,
+    /// The back-end couldn't allocate memory for a resource.
+    OutOfMemory,
     StackUnbalance,
-    BackendError(Box<dyn std::error::Error>),
+    BackendError(Box<dyn std::error::Error + Send + Sync>),
     #[doc(hidden)]
     _NonExhaustive,
 }
 
+impl Error {
+    /// The kind of error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    /// The name of the drawing operation that failed, if known.
+    ///
+    /// This is set for errors from operations that can't return errors
+    /// themselves, such as `fill`, which are reported later by `status`
+    /// or `finish`.
+    pub fn op(&self) -> Option<&'static str> {
+        self.0.op
+    }
+
+    /// A description of what went wrong, if there is one.
+    pub fn context(&self) -> Option<&str> {
+        self.0.context.as_ref().map(String::as_str)
+    }
+
+    /// Attach the name of the drawing operation that failed.
+    pub fn with_op(mut self, op: &'static str) -> Error {
+        self.0.op = Some(op);
+        self
+    }
+
+    /// Attach a description of what went wrong, such as the invalid value.
+    pub fn with_context(mut self, context: impl Into<String>) -> Error {
+        self.0.context = Some(context.into());
+        self
+    }
+}
+
+/// Fails to compile if errors stop being `Send` and `Sync`.
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<Error>();
+}
+
 /// Create a new error of the given kind.
 pub fn new_error(kind: ErrorKind) -> Error {
-    Error(Box::new(kind))
+    Error(Box::new(ErrorInner {
+        kind,
+        op: None,
+        context: None,
+    }))
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self.0 {
-            ErrorKind::InvalidInput => write!(f, "Invalid input"),
-            ErrorKind::NotSupported => write!(f, "Option not supported"),
-            ErrorKind::StackUnbalance => write!(f, "Stack unbalanced"),
+        if let Some(op) = self.0.op {
+            write!(f, "{}: ", op)?;
+        }
+        match self.0.kind {
+            ErrorKind::InvalidInput => write!(f, "Invalid input")?,
+            ErrorKind::InvalidImageSize => write!(f, "Invalid image size")?,
+            ErrorKind::NotSupported => write!(f, "Option not supported")?,
+            ErrorKind::MissingFeature(feature) => {
+                write!(f, "Requires the \"{}\" feature", feature)?
+            }
+            ErrorKind::FontLoadingFailed => write!(f, "Font loading failed")?,
+            ErrorKind::OutOfMemory => write!(f, "Out of memory")?,
+            ErrorKind::StackUnbalance => write!(f, "Stack unbalanced")?,
             ErrorKind::BackendError(ref e) => {
                 write!(f, "Backend error: ")?;
-                e.fmt(f)
+                e.fmt(f)?
             }
-            _ => write!(f, "Unknown piet error (case not covered)"),
+            _ => write!(f, "Unknown piet error (case not covered)")?,
+        }
+        if let Some(ref context) = self.0.context {
+            write!(f, " ({})", context)?;
         }
+        Ok(())
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.0.kind {
+            ErrorKind::BackendError(ref e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
 
-impl From<Box<dyn std::error::Error>> for Error {
-    fn from(e: Box<dyn std::error::Error>) -> Error {
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Error {
         new_error(ErrorKind::BackendError(e))
     }
 }
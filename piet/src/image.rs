@@ -0,0 +1,734 @@
+//! Pixel format conversions and validation for bitmap images.
+
+use std::borrow::Cow;
+
+use kurbo::{Affine, Rect};
+
+use crate::{affine_inverse, new_error, Error, ErrorKind, ImageFormat};
+
+/// An image made by a render context.
+pub trait Image {
+    /// The size of the image, in pixels.
+    fn size(&self) -> (usize, usize);
+
+    /// The format of the image's pixels, as stored.
+    ///
+    /// This is the format the image was made with, except that high bit
+    /// depth formats are stored as `RgbaSeparate` (see
+    /// [`image_bits_per_channel`](trait.RenderContext.html#method.image_bits_per_channel)).
+    /// Images drawn by piet, from offscreen targets or captures, are
+    /// `RgbaPremul`.
+    fn format(&self) -> ImageFormat;
+
+    /// Get the image's pixels, tightly packed in the given format.
+    ///
+    /// Formats without an alpha channel, `Rgb` and `Grayscale`, are
+    /// composited over black. Use
+    /// [`convert_from_rgba_premul`](fn.convert_from_rgba_premul.html) on
+    /// `RgbaPremul` pixels for another background.
+    ///
+    /// Back-ends that can't read images back report
+    /// `ErrorKind::NotSupported`.
+    fn to_raw_pixels(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
+        let _ = format;
+        Err(new_error(ErrorKind::NotSupported))
+    }
+}
+
+/// Multiply a color channel by alpha, rounding to nearest.
+pub fn premul(x: u8, a: u8) -> u8 {
+    let y = (x as u16) * (a as u16) + 0x80;
+    ((y + (y >> 8)) >> 8) as u8
+}
+
+/// Divide a premultiplied color channel by alpha, rounding to nearest.
+///
+/// Fully transparent pixels carry no color, so they map to 0.
+pub fn unpremul(x: u8, a: u8) -> u8 {
+    if a == 0 {
+        0
+    } else {
+        let y = (x as u32 * 255 + (a as u32 / 2)) / (a as u32);
+        y.min(255) as u8
+    }
+}
+
+/// The gray level of an RGB color, with Rec. 601 weights.
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29 + 128) >> 8) as u8
+}
+
+/// The 4x4 Bayer matrix, for ordered dithering.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantize a channel from 0.0 to 1.0 to 8 bits, dithered by pixel position.
+fn dither(v: f32, x: usize, y: usize) -> u8 {
+    let threshold = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0;
+    (v * 255.0 + threshold).floor().max(0.0).min(255.0) as u8
+}
+
+/// Read one channel of a high bit depth pixel, from 0.0 to 1.0.
+fn read_channel(bytes: &[u8], format: ImageFormat) -> f32 {
+    match format {
+        ImageFormat::Rgba16Separate => (bytes[0] as u16 | (bytes[1] as u16) << 8) as f32 / 65535.0,
+        _ => {
+            let bits = bytes[0] as u32
+                | (bytes[1] as u32) << 8
+                | (bytes[2] as u32) << 16
+                | (bytes[3] as u32) << 24;
+            let v = f32::from_bits(bits);
+            // This maps NaN to 0.0 as well.
+            if v > 0.0 {
+                v.min(1.0)
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+/// Down-convert a high bit depth image to 8 bits per channel.
+///
+/// The image is checked as by [`check_image_buf`](fn.check_image_buf.html),
+/// and returned tightly packed in `RgbaSeparate` format, with ordered
+/// dithering to hide banding. Only `Rgba16Separate` and `RgbaF32Separate`
+/// are accepted.
+pub fn dither_to_rgba_separate(
+    width: usize,
+    height: usize,
+    stride: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<Vec<u8>, Error> {
+    match format {
+        ImageFormat::Rgba16Separate | ImageFormat::RgbaF32Separate => (),
+        _ => return Err(new_error(ErrorKind::NotSupported)),
+    }
+    check_image_buf(width, height, stride, buf, format)?;
+    let channel_bytes = format.bits_per_channel() / 8;
+    let mut dst = vec![0; width * height * 4];
+    for y in 0..height {
+        let row = &buf[y * stride..y * stride + width * channel_bytes * 4];
+        for (i, s) in row.chunks(channel_bytes).enumerate() {
+            dst[y * width * 4 + i] = dither(read_channel(s, format), i / 4, y);
+        }
+    }
+    Ok(dst)
+}
+
+/// Convert premultiplied RGBA pixels to another format.
+///
+/// The `src` buffer holds tightly packed pixels in `RgbaPremul` format, and
+/// `dst` must have room for exactly the same number of pixels in `format`.
+/// Converting a whole image or a single row works the same way.
+///
+/// Formats without an alpha channel are composited over the opaque
+/// `background` color, given as 0xRRGGBB. Using black is the same as
+/// discarding alpha. High bit depth formats are widened from the 8-bit
+/// values, so they carry no extra precision.
+pub fn convert_from_rgba_premul(
+    src: &[u8],
+    dst: &mut [u8],
+    format: ImageFormat,
+    background: u32,
+) -> Result<(), Error> {
+    let n_pixels = src.len() / 4;
+    let bytes_per_pixel = match format {
+        ImageFormat::_NonExhaustive => return Err(new_error(ErrorKind::NotSupported)),
+        _ => format.bytes_per_pixel(),
+    };
+    if src.len() % 4 != 0 || dst.len() != n_pixels * bytes_per_pixel {
+        return Err(new_error(ErrorKind::InvalidInput));
+    }
+    let bg = [
+        (background >> 16) as u8,
+        (background >> 8) as u8,
+        background as u8,
+    ];
+    // Source over: the background shows through by (1 - alpha).
+    let over_bg = |s: &[u8], i: usize| s[i].saturating_add(premul(bg[i], 255 - s[3]));
+    match format {
+        ImageFormat::RgbaPremul => dst.copy_from_slice(src),
+        ImageFormat::RgbaSeparate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                let a = s[3];
+                d[0] = unpremul(s[0], a);
+                d[1] = unpremul(s[1], a);
+                d[2] = unpremul(s[2], a);
+                d[3] = a;
+            }
+        }
+        ImageFormat::BgraPremul => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                d[0] = s[2];
+                d[1] = s[1];
+                d[2] = s[0];
+                d[3] = s[3];
+            }
+        }
+        ImageFormat::BgraSeparate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                let a = s[3];
+                d[0] = unpremul(s[2], a);
+                d[1] = unpremul(s[1], a);
+                d[2] = unpremul(s[0], a);
+                d[3] = a;
+            }
+        }
+        ImageFormat::Rgb => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(3)) {
+                for i in 0..3 {
+                    d[i] = over_bg(s, i);
+                }
+            }
+        }
+        ImageFormat::Grayscale => {
+            for (s, d) in src.chunks(4).zip(dst.iter_mut()) {
+                *d = luma(over_bg(s, 0), over_bg(s, 1), over_bg(s, 2));
+            }
+        }
+        ImageFormat::Alpha8 => {
+            for (s, d) in src.chunks(4).zip(dst.iter_mut()) {
+                *d = s[3];
+            }
+        }
+        ImageFormat::Rgba16Separate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(8)) {
+                let a = s[3];
+                let channels = [unpremul(s[0], a), unpremul(s[1], a), unpremul(s[2], a), a];
+                for (c, d) in channels.iter().zip(d.chunks_mut(2)) {
+                    // Replicating the byte maps 255 to 65535.
+                    d[0] = *c;
+                    d[1] = *c;
+                }
+            }
+        }
+        ImageFormat::RgbaF32Separate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(16)) {
+                let a = s[3];
+                let channels = [unpremul(s[0], a), unpremul(s[1], a), unpremul(s[2], a), a];
+                for (c, d) in channels.iter().zip(d.chunks_mut(4)) {
+                    let bits = (*c as f32 / 255.0).to_bits();
+                    d[0] = bits as u8;
+                    d[1] = (bits >> 8) as u8;
+                    d[2] = (bits >> 16) as u8;
+                    d[3] = (bits >> 24) as u8;
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Convert pixels in any format to premultiplied RGBA.
+///
+/// The `src` buffer holds tightly packed pixels in `format`, and `dst` must
+/// have room for exactly the same number of pixels in `RgbaPremul` format.
+/// `Alpha8` pixels become black with that alpha. High bit depth formats
+/// aren't supported, as they need to be dithered with
+/// [`dither_to_rgba_separate`](fn.dither_to_rgba_separate.html) first.
+pub fn convert_to_rgba_premul(
+    src: &[u8],
+    dst: &mut [u8],
+    format: ImageFormat,
+) -> Result<(), Error> {
+    let bytes_per_pixel = match format {
+        ImageFormat::Rgba16Separate
+        | ImageFormat::RgbaF32Separate
+        | ImageFormat::_NonExhaustive => return Err(new_error(ErrorKind::NotSupported)),
+        _ => format.bytes_per_pixel(),
+    };
+    if src.len() % bytes_per_pixel != 0 || dst.len() != src.len() / bytes_per_pixel * 4 {
+        return Err(new_error(ErrorKind::InvalidInput));
+    }
+    match format {
+        ImageFormat::RgbaPremul => dst.copy_from_slice(src),
+        ImageFormat::RgbaSeparate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                let a = s[3];
+                d[0] = premul(s[0], a);
+                d[1] = premul(s[1], a);
+                d[2] = premul(s[2], a);
+                d[3] = a;
+            }
+        }
+        ImageFormat::BgraPremul => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                d[0] = s[2];
+                d[1] = s[1];
+                d[2] = s[0];
+                d[3] = s[3];
+            }
+        }
+        ImageFormat::BgraSeparate => {
+            for (s, d) in src.chunks(4).zip(dst.chunks_mut(4)) {
+                let a = s[3];
+                d[0] = premul(s[2], a);
+                d[1] = premul(s[1], a);
+                d[2] = premul(s[0], a);
+                d[3] = a;
+            }
+        }
+        ImageFormat::Rgb => {
+            for (s, d) in src.chunks(3).zip(dst.chunks_mut(4)) {
+                d[..3].copy_from_slice(s);
+                d[3] = 255;
+            }
+        }
+        ImageFormat::Grayscale => {
+            for (&g, d) in src.iter().zip(dst.chunks_mut(4)) {
+                d.copy_from_slice(&[g, g, g, 255]);
+            }
+        }
+        ImageFormat::Alpha8 => {
+            for (&a, d) in src.iter().zip(dst.chunks_mut(4)) {
+                d.copy_from_slice(&[0, 0, 0, a]);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Convert premultiplied ARGB pixels, stored as native `u32` values, to
+/// premultiplied RGBA.
+///
+/// This is how Raqote stores pixels, and how Cairo stores `ARgb32` pixels
+/// when viewed as `u32` values. The `dst` buffer must be exactly four times
+/// as long as `src`.
+pub fn argb32_to_rgba_premul(src: &[u32], dst: &mut [u8]) -> Result<(), Error> {
+    if dst.len() != src.len() * 4 {
+        return Err(new_error(ErrorKind::InvalidInput));
+    }
+    for (&argb, d) in src.iter().zip(dst.chunks_mut(4)) {
+        d[0] = (argb >> 16) as u8;
+        d[1] = (argb >> 8) as u8;
+        d[2] = argb as u8;
+        d[3] = (argb >> 24) as u8;
+    }
+    Ok(())
+}
+
+/// Check that a buffer holds an image of the given size, format and stride.
+///
+/// The image must not be empty, rows must be at least `stride` bytes apart,
+/// and the buffer must hold every row, although the last one needn't be
+/// padded. Failures are reported as `ErrorKind::InvalidInput`, with the
+/// reason as context.
+pub fn check_image_buf(
+    width: usize,
+    height: usize,
+    stride: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Result<(), Error> {
+    let invalid = |context: String| Err(new_error(ErrorKind::InvalidInput).with_context(context));
+    if width == 0 || height == 0 {
+        return invalid(format!("image size {}x{} is empty", width, height));
+    }
+    let row_bytes = match width.checked_mul(format.bytes_per_pixel()) {
+        Some(row_bytes) => row_bytes,
+        None => return invalid(format!("image width {} is too large", width)),
+    };
+    if stride < row_bytes {
+        return invalid(format!(
+            "stride {} is less than the row size {}",
+            stride, row_bytes
+        ));
+    }
+    match stride
+        .checked_mul(height - 1)
+        .and_then(|n| n.checked_add(row_bytes))
+    {
+        Some(needed) if buf.len() >= needed => Ok(()),
+        Some(needed) => invalid(format!(
+            "buffer has {} bytes, {}x{} image needs {}",
+            buf.len(),
+            width,
+            height,
+            needed
+        )),
+        None => invalid(format!("image size {}x{} is too large", width, height)),
+    }
+}
+
+/// The rows of an image buffer without padding.
+///
+/// The buffer is borrowed when it's already tightly packed. It must have
+/// passed [`check_image_buf`](fn.check_image_buf.html).
+pub fn packed_image_buf(
+    width: usize,
+    height: usize,
+    stride: usize,
+    buf: &[u8],
+    format: ImageFormat,
+) -> Cow<[u8]> {
+    let row_bytes = width * format.bytes_per_pixel();
+    if stride == row_bytes {
+        return Cow::from(&buf[..row_bytes * height]);
+    }
+    let mut packed = Vec::with_capacity(row_bytes * height);
+    for y in 0..height {
+        packed.extend_from_slice(&buf[y * stride..y * stride + row_bytes]);
+    }
+    Cow::from(packed)
+}
+
+/// Whether an image can be drawn from or into a rectangle.
+///
+/// The rectangle's width and height must be finite and not zero; anything
+/// else would scale the image to nothing or to infinity.
+pub fn is_drawable_rect(rect: Rect) -> bool {
+    let (width, height) = (rect.width(), rect.height());
+    width != 0.0 && height != 0.0 && width.is_finite() && height.is_finite()
+}
+
+/// The whole pixels of an image that a source rectangle touches.
+///
+/// The rectangle is rounded out to whole pixels and limited to the image.
+/// `None` means that nothing is left, or that the rectangle isn't
+/// [drawable](fn.is_drawable_rect.html), so there is nothing to draw.
+pub fn image_src_area(src_rect: Rect, width: usize, height: usize) -> Option<Rect> {
+    if !is_drawable_rect(src_rect) {
+        return None;
+    }
+    let x0 = src_rect.x0.min(src_rect.x1).floor().max(0.0);
+    let y0 = src_rect.y0.min(src_rect.y1).floor().max(0.0);
+    let x1 = src_rect.x0.max(src_rect.x1).ceil().min(width as f64);
+    let y1 = src_rect.y0.max(src_rect.y1).ceil().min(height as f64);
+    if x0 < x1 && y0 < y1 {
+        Some(Rect::new(x0, y0, x1, y1))
+    } else {
+        None
+    }
+}
+
+/// The whole pixels of a target that a rectangle covers, for capturing.
+///
+/// The rectangle is rounded out to whole pixels, but not limited to the
+/// target. An empty or infinite rectangle is reported as
+/// `ErrorKind::InvalidInput`.
+pub fn capture_pixel_area(rect: Rect) -> Result<Rect, Error> {
+    let x0 = rect.x0.min(rect.x1).floor();
+    let y0 = rect.y0.min(rect.y1).floor();
+    let x1 = rect.x0.max(rect.x1).ceil();
+    let y1 = rect.y0.max(rect.y1).ceil();
+    if rect.x0 == rect.x1 || rect.y0 == rect.y1 || !(x0 < x1 && y0 < y1) {
+        return Err(new_error(ErrorKind::InvalidInput).with_context("capture area is empty"));
+    }
+    if !(x1 - x0).is_finite() || !(y1 - y0).is_finite() {
+        return Err(new_error(ErrorKind::InvalidInput).with_context("capture area is infinite"));
+    }
+    Ok(Rect::new(x0, y0, x1, y1))
+}
+
+/// The whole pixels of an image to update, from a dirty rectangle.
+///
+/// The rectangle is rounded out to whole pixels, and must be non-empty and
+/// lie within the image; otherwise the error is `ErrorKind::InvalidInput`.
+pub fn image_dirty_area(dirty_rect: Rect, width: usize, height: usize) -> Result<Rect, Error> {
+    let area = capture_pixel_area(dirty_rect)
+        .map_err(|_| new_error(ErrorKind::InvalidInput).with_context("dirty area is empty"))?;
+    if area.x0 < 0.0 || area.y0 < 0.0 || area.x1 > width as f64 || area.y1 > height as f64 {
+        return Err(new_error(ErrorKind::InvalidInput).with_context(format!(
+            "dirty area {:?} is outside the {}x{} image",
+            (area.x0, area.y0, area.x1, area.y1),
+            width,
+            height
+        )));
+    }
+    Ok(area)
+}
+
+/// The mip level for drawing part of an image into a rectangle.
+///
+/// `transform` maps the rectangle to device pixels. The level is how many
+/// times the image can be halved while neighboring device pixels stay at
+/// least one of its pixels apart, so bilinear sampling doesn't skip any.
+pub fn mip_level(transform: Affine, src_rect: Rect, dst_rect: Rect) -> usize {
+    let inverse = match affine_inverse(transform) {
+        Some(inverse) => inverse,
+        None => return 0,
+    };
+    if dst_rect.width() == 0.0 || dst_rect.height() == 0.0 {
+        return 0;
+    }
+    let c = inverse.as_coeffs();
+    let scale_x = src_rect.width() / dst_rect.width();
+    let scale_y = src_rect.height() / dst_rect.height();
+    // The distances in image pixels of one device pixel across and down.
+    let step_x = (c[0] * scale_x).hypot(c[1] * scale_y);
+    let step_y = (c[2] * scale_x).hypot(c[3] * scale_y);
+    let step = step_x.max(step_y);
+    if step.is_nan() || step < 2.0 {
+        return 0;
+    }
+    step.log2().floor().min(32.0) as usize
+}
+
+/// Halve an image of 4-byte premultiplied pixels, averaging 2x2 blocks.
+///
+/// Odd sizes round up, with the last row or column averaged with itself.
+/// Returns the new width, height and pixels.
+pub fn halve_image_buf(width: usize, height: usize, buf: &[u8]) -> (usize, usize, Vec<u8>) {
+    let half_width = (width + 1) / 2;
+    let half_height = (height + 1) / 2;
+    let mut half = Vec::with_capacity(half_width * half_height * 4);
+    for y in 0..half_height {
+        let rows = [2 * y, (2 * y + 1).min(height - 1)];
+        for x in 0..half_width {
+            let columns = [2 * x, (2 * x + 1).min(width - 1)];
+            for c in 0..4 {
+                let mut sum = 2;
+                for &row in &rows {
+                    for &column in &columns {
+                        sum += buf[(row * width + column) * 4 + c] as u32;
+                    }
+                }
+                half.push((sum / 4) as u8);
+            }
+        }
+    }
+    (half_width, half_height, half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premul_rounds_to_nearest() {
+        for a in 0..=255u32 {
+            for x in 0..=255u32 {
+                let expected = (x * a * 2 + 255) / 510;
+                assert_eq!(premul(x as u8, a as u8) as u32, expected, "{} * {}", x, a);
+            }
+        }
+    }
+
+    #[test]
+    fn premul_round_trips() {
+        for &a in &[0u8, 1, 128, 255] {
+            // Every premultiplied value survives un-premultiplying.
+            for p in 0..=a {
+                assert_eq!(premul(unpremul(p, a), a), p, "{} at alpha {}", p, a);
+            }
+        }
+        for x in 0..=255 {
+            assert_eq!(premul(x, 0), 0);
+            assert_eq!(unpremul(x, 0), 0);
+            assert_eq!(unpremul(premul(x, 255), 255), x);
+            let half = unpremul(premul(x, 128), 128) as i16;
+            assert!((half - x as i16).abs() <= 1, "{} at alpha 128", x);
+        }
+        assert_eq!(unpremul(1, 1), 255);
+        assert_eq!(unpremul(64, 128), 128);
+    }
+
+    #[test]
+    fn rgba_separate_from_premul() {
+        let src = [64, 0, 32, 128, 0, 0, 0, 0, 10, 20, 30, 255];
+        let mut dst = [0; 12];
+        convert_from_rgba_premul(&src, &mut dst, ImageFormat::RgbaSeparate, 0).unwrap();
+        assert_eq!(dst, [128, 0, 64, 128, 0, 0, 0, 0, 10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn rgb_composites_over_background() {
+        // Transparent, half-transparent red, and opaque green.
+        let src = [0, 0, 0, 0, 128, 0, 0, 128, 0, 255, 0, 255];
+        let mut dst = [0; 9];
+        convert_from_rgba_premul(&src, &mut dst, ImageFormat::Rgb, 0x33_66_99).unwrap();
+        assert_eq!(dst, [0x33, 0x66, 0x99, 153, 51, 76, 0, 255, 0]);
+        convert_from_rgba_premul(&src, &mut dst, ImageFormat::Rgb, 0xff_ff_ff).unwrap();
+        assert_eq!(dst, [255, 255, 255, 255, 127, 127, 0, 255, 0]);
+        // A black background discards alpha.
+        convert_from_rgba_premul(&src, &mut dst, ImageFormat::Rgb, 0).unwrap();
+        assert_eq!(dst, [0, 0, 0, 128, 0, 0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn argb32_is_read_as_rgba() {
+        let mut rgba = [0; 8];
+        argb32_to_rgba_premul(&[0x80_40_20_10, 0xFF_00_00_FF], &mut rgba).unwrap();
+        assert_eq!(rgba, [0x40, 0x20, 0x10, 0x80, 0x00, 0x00, 0xFF, 0xFF]);
+        assert!(argb32_to_rgba_premul(&[0], &mut rgba).is_err());
+    }
+
+    #[test]
+    fn convert_checks_buffer_sizes() {
+        let mut dst = [0; 3];
+        let result = convert_from_rgba_premul(&[0; 8], &mut dst, ImageFormat::Rgb, 0);
+        assert!(result.is_err());
+        let result = convert_to_rgba_premul(&[0; 3], &mut dst, ImageFormat::Rgb);
+        assert!(result.is_err());
+    }
+
+    fn is_invalid_input(e: &Error) -> bool {
+        match *e.kind() {
+            ErrorKind::InvalidInput => true,
+            _ => false,
+        }
+    }
+
+    /// Pixels of four equal channels, as little-endian bytes.
+    fn high_bit_depth_buf(channels: &[u64], bytes: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &channel in channels {
+            for _ in 0..4 {
+                for i in 0..bytes {
+                    buf.push((channel >> (8 * i)) as u8);
+                }
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn dither_spreads_the_remainder() {
+        // 25764 / 65535 is a quarter of the way from 100 to 101 in 8 bits.
+        let buf = high_bit_depth_buf(&[25764; 16], 2);
+        let dst = dither_to_rgba_separate(4, 4, 4 * 8, &buf, ImageFormat::Rgba16Separate).unwrap();
+        let up = dst.iter().filter(|&&v| v == 101).count();
+        assert_eq!(up, 16);
+        assert!(dst.iter().all(|&v| v == 100 || v == 101));
+        // The same pixel positions are rounded up in every channel.
+        for pixel in dst.chunks(4) {
+            assert!(pixel.iter().all(|&v| v == pixel[0]));
+        }
+    }
+
+    #[test]
+    fn dither_keeps_exact_values() {
+        let levels = [0, 1, 128, 254, 255];
+        let channels: Vec<u64> = levels.iter().map(|&v| v * 257).collect();
+        let buf = high_bit_depth_buf(&channels, 2);
+        let dst = dither_to_rgba_separate(5, 1, 5 * 8, &buf, ImageFormat::Rgba16Separate).unwrap();
+        for (pixel, &level) in dst.chunks(4).zip(&levels) {
+            assert_eq!(pixel, [level as u8; 4]);
+        }
+    }
+
+    #[test]
+    fn dither_clamps_floats() {
+        let values = [
+            std::f32::NAN,
+            -1.0,
+            -0.0,
+            2.0,
+            std::f32::INFINITY,
+            std::f32::NEG_INFINITY,
+        ];
+        let channels: Vec<u64> = values.iter().map(|v| v.to_bits() as u64).collect();
+        let buf = high_bit_depth_buf(&channels, 4);
+        let dst =
+            dither_to_rgba_separate(6, 1, 6 * 16, &buf, ImageFormat::RgbaF32Separate).unwrap();
+        let expected = [0, 0, 0, 255, 255, 0];
+        for (pixel, &level) in dst.chunks(4).zip(&expected) {
+            assert_eq!(pixel, [level; 4]);
+        }
+    }
+
+    #[test]
+    fn dither_checks_its_input() {
+        let buf = high_bit_depth_buf(&[0], 2);
+        let result = dither_to_rgba_separate(1, 1, 8, &buf, ImageFormat::RgbaSeparate);
+        match *result.unwrap_err().kind() {
+            ErrorKind::NotSupported => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        let result = dither_to_rgba_separate(2, 1, 16, &buf, ImageFormat::Rgba16Separate);
+        match *result.unwrap_err().kind() {
+            ErrorKind::InvalidInput => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn src_area_rounds_out_within_the_image() {
+        let area = image_src_area(Rect::new(0.5, 0.25, 1.5, 0.75), 4, 1).unwrap();
+        assert_eq!((area.x0, area.y0, area.x1, area.y1), (0.0, 0.0, 2.0, 1.0));
+        let area = image_src_area(Rect::new(3.0, 0.0, -1.0, 1.0), 2, 1).unwrap();
+        assert_eq!((area.x0, area.y0, area.x1, area.y1), (0.0, 0.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn src_area_rejects_degenerate_rects() {
+        let inf = std::f64::INFINITY;
+        for &rect in &[
+            Rect::new(0.2, 0.0, 0.2, 1.0),
+            Rect::new(0.0, 0.5, 1.0, 0.5),
+            Rect::new(0.0, 0.0, inf, 1.0),
+            Rect::new(0.0, std::f64::NAN, 1.0, 1.0),
+            Rect::new(2.0, 0.0, 3.0, 1.0),
+        ] {
+            assert!(image_src_area(rect, 2, 1).is_none(), "{:?}", rect);
+        }
+        assert!(!is_drawable_rect(Rect::new(0.0, 0.0, 0.0, 1.0)));
+        assert!(is_drawable_rect(Rect::new(1.0, 1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn capture_area_rounds_out() {
+        let area = capture_pixel_area(Rect::new(0.5, 1.0, 2.25, 3.75)).unwrap();
+        assert_eq!((area.x0, area.y0, area.x1, area.y1), (0.0, 1.0, 3.0, 4.0));
+        // Flipped and negative rectangles are normalized, not clamped.
+        let area = capture_pixel_area(Rect::new(2.0, -0.5, -1.5, -3.0)).unwrap();
+        assert_eq!((area.x0, area.y0, area.x1, area.y1), (-2.0, -3.0, 2.0, 0.0));
+        let area = capture_pixel_area(Rect::new(0.25, 0.25, 0.5, 0.5)).unwrap();
+        assert_eq!((area.x0, area.y0, area.x1, area.y1), (0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn capture_area_rejects_empty_and_infinite_rects() {
+        let rects = [
+            Rect::new(1.0, 0.0, 1.0, 5.0),
+            Rect::new(0.0, 2.5, 5.0, 2.5),
+            Rect::new(std::f64::NAN, 0.0, 1.0, 1.0),
+            Rect::new(0.0, 0.0, std::f64::INFINITY, 1.0),
+        ];
+        for &rect in &rects {
+            match capture_pixel_area(rect) {
+                Err(ref e) if is_invalid_input(e) => (),
+                result => panic!("{:?} gave {:?}", rect, result.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn mip_level_follows_the_scale() {
+        let src = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let identity = Affine::default();
+        assert_eq!(mip_level(identity, src, src), 0);
+        assert_eq!(
+            mip_level(identity, src, Rect::new(0.0, 0.0, 200.0, 200.0)),
+            0
+        );
+        assert_eq!(mip_level(identity, src, Rect::new(0.0, 0.0, 50.0, 50.0)), 1);
+        assert_eq!(mip_level(identity, src, Rect::new(0.0, 0.0, 30.0, 30.0)), 1);
+        assert_eq!(
+            mip_level(identity, src, Rect::new(0.0, 0.0, 10.0, 100.0)),
+            3
+        );
+        // The context's transform counts too.
+        let dst = Rect::new(0.0, 0.0, 100.0, 100.0);
+        assert_eq!(mip_level(Affine::scale(0.25), src, dst), 2);
+        assert_eq!(mip_level(Affine::scale(0.0), src, dst), 0);
+        assert_eq!(mip_level(identity, src, Rect::new(0.0, 0.0, 0.0, 1.0)), 0);
+    }
+
+    #[test]
+    fn halving_averages_blocks() {
+        let buf = [
+            0, 0, 0, 255, 255, 255, 255, 255, 10, 20, 30, 40, //
+            255, 255, 255, 255, 0, 0, 0, 255, 10, 20, 30, 40, //
+        ];
+        let (width, height, half) = halve_image_buf(3, 2, &buf);
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(half, vec![128, 128, 128, 255, 10, 20, 30, 40]);
+        let (width, height, half) = halve_image_buf(2, 1, &half);
+        assert_eq!((width, height), (1, 1));
+        assert_eq!(half, vec![69, 74, 79, 148]);
+    }
+}
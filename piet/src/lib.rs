@@ -1,15 +1,19 @@
 //! A 2D graphics abstraction.
 
 mod conv;
+mod decode;
 mod error;
 mod gradient;
+mod image;
 mod render_context;
 mod shapes;
 mod text;
 
 pub use crate::conv::*;
+pub use crate::decode::*;
 pub use crate::error::*;
 pub use crate::gradient::*;
+pub use crate::image::*;
 pub use crate::render_context::*;
 pub use crate::shapes::*;
 pub use crate::text::*;
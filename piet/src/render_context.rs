@@ -1,8 +1,11 @@
 //! The main render context trait.
 
-use kurbo::{Affine, Rect, Shape, Vec2};
+use kurbo::{Affine, BezPath, Rect, Shape, Vec2};
 
-use crate::{Error, FillRule, Gradient, RoundFrom, RoundInto, StrokeStyle, Text, TextLayout};
+use crate::{
+    new_error, Error, ErrorKind, FillRule, Gradient, Image, RoundFrom, RoundInto, StrokeStyle,
+    Text, TextLayout,
+};
 
 /// A requested interpolation mode for drawing images.
 #[derive(Clone, Copy, PartialEq)]
@@ -11,6 +14,80 @@ pub enum InterpolationMode {
     NearestNeighbor,
     /// Use bilinear interpolation.
     Bilinear,
+    /// Use bicubic interpolation, which is sharper when scaling up.
+    ///
+    /// Back-ends without it fall back to `Bilinear`.
+    Bicubic,
+    /// Use the best filter available, averaging source pixels when scaling
+    /// down so that large reductions don't alias.
+    ///
+    /// This may be much slower than the other modes. Back-ends without it
+    /// fall back to `Bilinear`.
+    HighQuality,
+}
+
+/// Options for drawing images, beyond the destination rectangle.
+#[derive(Clone)]
+pub struct DrawImageOptions {
+    opacity: f64,
+    transform: Affine,
+    clip: Option<BezPath>,
+}
+
+impl DrawImageOptions {
+    /// Options that draw the image as [`draw_image`](trait.RenderContext.html#tymethod.draw_image) does.
+    pub fn new() -> DrawImageOptions {
+        DrawImageOptions {
+            opacity: 1.0,
+            transform: Affine::default(),
+            clip: None,
+        }
+    }
+
+    /// Set the opacity of the image.
+    ///
+    /// The value is clamped to the range 0.0 to 1.0, and NaN is treated as 0.0.
+    pub fn set_opacity(&mut self, opacity: f64) {
+        self.opacity = opacity.max(0.0).min(1.0);
+    }
+
+    /// Set a transform applied to the image and its destination rectangle,
+    /// on top of the context's transform.
+    ///
+    /// A transform without an inverse, such as a scale by zero, or with
+    /// coefficients that aren't finite, draws nothing.
+    pub fn set_transform(&mut self, transform: Affine) {
+        self.transform = transform;
+    }
+
+    /// Set a shape the image is clipped to, with the non-zero fill rule.
+    ///
+    /// It is in the context's coordinates, so the options' transform doesn't
+    /// apply to it.
+    pub fn set_clip(&mut self, clip: BezPath) {
+        self.clip = Some(clip);
+    }
+
+    /// The opacity of the image, from 0.0 to 1.0.
+    pub fn opacity(&self) -> f64 {
+        self.opacity
+    }
+
+    /// The transform applied to the image and its destination rectangle.
+    pub fn transform(&self) -> Affine {
+        self.transform
+    }
+
+    /// The shape the image is clipped to, if any.
+    pub fn clip(&self) -> Option<&BezPath> {
+        self.clip.as_ref()
+    }
+}
+
+impl Default for DrawImageOptions {
+    fn default() -> DrawImageOptions {
+        DrawImageOptions::new()
+    }
 }
 
 /// The pixel format for bitmap images.
@@ -22,6 +99,29 @@ pub enum ImageFormat {
     RgbaSeparate,
     /// 4 bytes per pixel, in RGBA order, with premultiplied alpha.
     RgbaPremul,
+    /// 1 byte per pixel, the gray level of an opaque pixel.
+    Grayscale,
+    /// 1 byte per pixel, only alpha.
+    ///
+    /// Drawn as an image, this is black with the given alpha. It can also
+    /// serve as a coverage mask; see [`draw_mask`](trait.RenderContext.html#tymethod.draw_mask).
+    Alpha8,
+    /// 4 bytes per pixel, in BGRA order, with premultiplied alpha.
+    ///
+    /// This is the native layout of Cairo and Raqote surfaces on
+    /// little-endian machines.
+    BgraPremul,
+    /// 4 bytes per pixel, in BGRA order, with separate alpha.
+    BgraSeparate,
+    /// 8 bytes per pixel, in RGBA order, with separate alpha.
+    ///
+    /// Each channel is a little-endian `u16`.
+    Rgba16Separate,
+    /// 16 bytes per pixel, in RGBA order, with separate alpha.
+    ///
+    /// Each channel is a little-endian `f32`, nominally from 0.0 to 1.0.
+    /// Values outside that range are clamped, and NaN is treated as 0.0.
+    RgbaF32Separate,
     /// More formats may be added later.
     #[doc(hidden)]
     _NonExhaustive,
@@ -30,11 +130,26 @@ pub enum ImageFormat {
 impl ImageFormat {
     pub fn bytes_per_pixel(&self) -> usize {
         match *self {
+            ImageFormat::Grayscale | ImageFormat::Alpha8 => 1,
             ImageFormat::Rgb => 3,
-            ImageFormat::RgbaPremul | ImageFormat::RgbaSeparate => 4,
+            ImageFormat::RgbaPremul
+            | ImageFormat::RgbaSeparate
+            | ImageFormat::BgraPremul
+            | ImageFormat::BgraSeparate => 4,
+            ImageFormat::Rgba16Separate => 8,
+            ImageFormat::RgbaF32Separate => 16,
             _ => panic!(),
         }
     }
+
+    /// The number of bits in each channel of a pixel.
+    pub fn bits_per_channel(&self) -> usize {
+        match *self {
+            ImageFormat::Rgba16Separate => 16,
+            ImageFormat::RgbaF32Separate => 32,
+            _ => 8,
+        }
+    }
 }
 
 /// The main trait for rendering graphics.
@@ -71,13 +186,22 @@ pub trait RenderContext {
     type TextLayout: TextLayout;
 
     /// The associated type of an image.
-    type Image;
+    type Image: Image;
+
+    /// A target for drawing into an image, which can then be drawn on this
+    /// context.
+    type Offscreen: Offscreen<Image = Self::Image>;
 
     /// Report an internal error.
     ///
     /// Drawing operations may cause internal errors, which may also occur
     /// asynchronously after the drawing command was issued. This method reports
     /// any such error that has been detected.
+    ///
+    /// Operations that don't return a `Result`, such as `fill`, keep the first
+    /// error they encounter, tagged with the operation name (see
+    /// [`Error::op`](struct.Error.html#method.op)). That error is reported, and
+    /// cleared, by this method and by [`finish`](#method.finish).
     fn status(&mut self) -> Result<(), Error>;
 
     /// Create a new brush resource.
@@ -171,17 +295,143 @@ pub trait RenderContext {
     fn transform(&mut self, transform: Affine);
 
     /// Create a new image from a pixel buffer.
+    ///
+    /// The buffer holds tightly packed rows of pixels in the given format. A
+    /// buffer that is too short, or an empty image, is reported as
+    /// `ErrorKind::InvalidInput`.
     fn make_image(
         &mut self,
         width: usize,
         height: usize,
         buf: &[u8],
         format: ImageFormat,
+    ) -> Result<Self::Image, Error> {
+        let stride = width
+            .checked_mul(format.bytes_per_pixel())
+            .ok_or_else(|| new_error(ErrorKind::InvalidInput).with_context("image too large"))?;
+        self.make_image_with_stride(width, height, stride, buf, format)
+    }
+
+    /// The number of bits per channel kept for images made from `format`.
+    ///
+    /// Back-ends with 8-bit surfaces dither formats with more precision down
+    /// to 8 bits; no back-end currently keeps more than that. Reading pixels
+    /// back in such a format widens the 8-bit values again.
+    fn image_bits_per_channel(&self, format: ImageFormat) -> usize {
+        format.bits_per_channel().min(8)
+    }
+
+    /// Create a new image from a pixel buffer with padded rows.
+    ///
+    /// Rows start `stride` bytes apart in the buffer, which must be at least
+    /// the size of a row. See [`check_image_buf`](fn.check_image_buf.html)
+    /// for the validation every back-end does.
+    fn make_image_with_stride(
+        &mut self,
+        width: usize,
+        height: usize,
+        stride: usize,
+        buf: &[u8],
+        format: ImageFormat,
     ) -> Result<Self::Image, Error>;
 
+    /// Replace part of an image's pixels.
+    ///
+    /// The `dirty_rect` is in image pixels, and is rounded out to whole
+    /// pixels, which must lie within the image. The buffer holds tightly
+    /// packed rows of just that area, in a format that needn't be the one the
+    /// image was made with. Back-ends update the image in place where they
+    /// can, which is much cheaper than making a new one, for example for
+    /// every frame of a video.
+    fn update_image(
+        &mut self,
+        image: &mut Self::Image,
+        dirty_rect: impl Into<Rect>,
+        buf: &[u8],
+        format: ImageFormat,
+    ) -> Result<(), Error>;
+
     /// Draw an image.
     ///
     /// The image is scaled to the provided `rect`. It will be squashed if
     /// aspect ratios don't match.
     fn draw_image(&mut self, image: &Self::Image, rect: impl Into<Rect>, interp: InterpolationMode);
+
+    /// Draw an image with an opacity, transform or clip.
+    ///
+    /// This is [`draw_image`](#tymethod.draw_image) with the extra
+    /// [`DrawImageOptions`](struct.DrawImageOptions.html), which leave the
+    /// context's own state unchanged.
+    fn draw_image_with_options(
+        &mut self,
+        image: &Self::Image,
+        rect: impl Into<Rect>,
+        interp: InterpolationMode,
+        options: &DrawImageOptions,
+    );
+
+    /// Draw part of an image.
+    ///
+    /// The `src_rect` is in image pixels, and is scaled to `dst_rect`. Pixels
+    /// outside `src_rect`, rounded out to whole pixels, never show up in the
+    /// result, even with bilinear filtering: the edges of the area are
+    /// extended instead. This makes it safe to draw entries of an atlas or
+    /// sprite sheet.
+    fn draw_image_area(
+        &mut self,
+        image: &Self::Image,
+        src_rect: impl Into<Rect>,
+        dst_rect: impl Into<Rect>,
+        interp: InterpolationMode,
+    );
+
+    /// Fill a rectangle with a brush, using an image as a coverage mask.
+    ///
+    /// The image is scaled to the provided `rect` as in
+    /// [`draw_image`](#tymethod.draw_image), and only its alpha is used. This
+    /// is mostly useful with `ImageFormat::Alpha8` images, such as glyph
+    /// atlases.
+    ///
+    /// Brushes a back-end can't paint through a mask are reported as
+    /// `ErrorKind::NotSupported`. For now that's gradients in the Raqote
+    /// back-end, and any brush in the Direct2D back-end.
+    fn draw_mask(&mut self, mask: &Self::Image, rect: impl Into<Rect>, brush: &Self::Brush);
+
+    /// Create an offscreen target for drawing into an image.
+    ///
+    /// The target shares this context's device, so the resulting image can
+    /// be drawn efficiently, for example to cache static content between
+    /// frames. The image starts out transparent. A size the back-end can't
+    /// make an image of is reported as `ErrorKind::InvalidImageSize`, and
+    /// back-ends that can't do this report `ErrorKind::NotSupported`.
+    fn create_offscreen(&mut self, width: usize, height: usize) -> Result<Self::Offscreen, Error>;
+
+    /// Copy pixels that have already been drawn into a new image.
+    ///
+    /// The `rect` is in the target's pixels, ignoring the current transform,
+    /// and is rounded out to whole pixels. Parts of it outside the target
+    /// come out transparent. An empty `rect` is reported as
+    /// `ErrorKind::InvalidInput`, and back-ends that can't read their target
+    /// back report `ErrorKind::NotSupported`.
+    fn capture_image_area(&mut self, rect: impl Into<Rect>) -> Result<Self::Image, Error>;
+}
+
+/// A target for drawing into an image.
+///
+/// These are made by [`RenderContext::create_offscreen`](trait.RenderContext.html#tymethod.create_offscreen).
+pub trait Offscreen {
+    /// The type of the image this produces.
+    type Image: Image;
+
+    /// The render context for drawing into the image.
+    type Context: RenderContext<Image = Self::Image>;
+
+    /// The render context for drawing into the image.
+    fn render_context(&mut self) -> &mut Self::Context;
+
+    /// Finish drawing, and turn the target into an image.
+    ///
+    /// This finishes the render context, so errors from drawing are reported
+    /// here, as by [`RenderContext::finish`](trait.RenderContext.html#tymethod.finish).
+    fn into_image(self) -> Result<Self::Image, Error>;
 }